@@ -0,0 +1,310 @@
+//! cli/main.rs
+//! ----------------------------------------------------------------
+//! Native command line tool for batch processing world.json files
+//! on a server without the browser editor. Build with:
+//!     cargo build --release --no-default-features --features cli
+//!
+//! Usage: nodes-world <command> <world.json> [options]
+//! See `USAGE` below for commands and options.
+
+extern crate wasm_main;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::process;
+
+use wasm_main::territory::generator::GeneratorOptions;
+use wasm_main::territory::log::{LogLevel, set_log_hook};
+use wasm_main::territory::validate::ValidationOptions;
+use wasm_main::territory::world::{ColoringAlgorithm, MergeStrategy, World};
+
+const USAGE: &str = "\
+Usage: nodes-world <command> <world.json> [options]
+
+Commands:
+    validate        print validation issues as json, exits with 1 if any
+                    --min-size N          report territories smaller than N chunks
+    recolor         regenerate territory colors
+                    --algorithm A         stable (default), planar5, greedy6
+    neighbors       recalculate territory neighbors and edge flags
+    subdivide       subdivide territory into random territories
+                    --id ID               territory to subdivide (required)
+                    --radius R            average territory radius in chunks (4)
+                    --scale-x S, --scale-y S  cell scale (1)
+                    --seed S              random seed (0)
+                    --smooth-centers N    center smoothing iterations (2)
+                    --smooth-corners N    corner smoothing iterations (2)
+                    --delete-smaller-than N  (0)
+                    --merge-smaller-than N   (0)
+    merge-small     merge territories smaller than threshold into neighbors
+                    --threshold N         size threshold in chunks (required)
+                    --strategy S          smallest (default), border, compact
+    stats           print territory shape statistics as json
+                    --ids 1,2,...         territories (default all)
+    export          export world in another format
+                    --format F            graph-json, graph-dot, snapshot, world-json
+
+Options:
+    -o, --output PATH   output file (default stdout)
+    --grid-scale N      blocks per chunk (16)
+
+Commands that edit the world (recolor, neighbors, subdivide, merge-small)
+write world.json with updated neighbors. subdivide and merge-small also
+recolor territories with the stable coloring used by the editor on save.
+";
+
+// command line arguments: positional args and --option values
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse(args: Vec<String>) -> Result<Args, String> {
+        let mut positional: Vec<String> = Vec::new();
+        let mut options: HashMap<String, String> = HashMap::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg.starts_with("-") && arg.len() > 1 {
+                let name = match arg.as_str() {
+                    "-o" => "output".to_string(),
+                    _ => arg.trim_start_matches('-').to_string(),
+                };
+                let value = args.next().ok_or_else(|| format!("missing value for {}", arg))?;
+                options.insert(name, value);
+            } else {
+                positional.push(arg);
+            }
+        }
+
+        Ok(Args {
+            positional,
+            options,
+        })
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|s| s.as_str())
+    }
+
+    fn parse_or<T: std::str::FromStr>(&self, name: &str, default: T) -> Result<T, String> {
+        match self.get(name) {
+            Some(value) => value.parse::<T>().map_err(|_| format!("invalid value for --{}: {}", name, value)),
+            None => Ok(default),
+        }
+    }
+
+    fn parse_required<T: std::str::FromStr>(&self, name: &str) -> Result<T, String> {
+        let value = self.get(name).ok_or_else(|| format!("missing required option --{}", name))?;
+        value.parse::<T>().map_err(|_| format!("invalid value for --{}: {}", name, value))
+    }
+}
+
+fn stderr_log_hook(level: LogLevel, msg: &str) {
+    eprintln!("[{:?}] {}", level, msg);
+}
+
+fn load_world(path: &str, grid_scale: i32) -> Result<World, String> {
+    let json = std::fs::read_to_string(path).map_err(|err| format!("cannot read {}: {}", path, err))?;
+    let mut world = World::from_world_json(&json, grid_scale).map_err(|err| format!("{}: {}", path, err))?;
+    world.calculate_neighbors();
+    Ok(world)
+}
+
+fn write_output(path: Option<&str>, data: &[u8]) -> Result<(), String> {
+    match path {
+        Some(path) => std::fs::write(path, data).map_err(|err| format!("cannot write {}: {}", path, err)),
+        None => {
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            out.write_all(data)
+                .and_then(|_| out.write_all(b"\n"))
+                .map_err(|err| format!("cannot write output: {}", err))
+        },
+    }
+}
+
+fn write_world(world: &mut World, args: &Args) -> Result<(), String> {
+    world.update_neighbors();
+    write_output(args.get("output"), world.to_world_json().as_bytes())
+}
+
+fn run(command: &str, args: &Args) -> Result<i32, String> {
+    let input = args.positional.first().ok_or_else(|| "missing input world.json".to_string())?;
+    let grid_scale: i32 = args.parse_or("grid-scale", 16)?;
+    let mut world = load_world(input, grid_scale)?;
+
+    match command {
+        "validate" => {
+            let mut options = ValidationOptions::new();
+            options.min_size = args.parse_or("min-size", 0)?;
+            let issues = world.validate(Some(options));
+            write_output(args.get("output"), issues.as_bytes())?;
+            if issues != "[]" {
+                return Ok(1);
+            }
+        },
+        "recolor" => {
+            match args.get("algorithm").unwrap_or("stable") {
+                "stable" => { world.generate_colors_stable(); },
+                "planar5" => world.generate_colors(Some(ColoringAlgorithm::Planar5)),
+                "greedy6" => world.generate_colors(Some(ColoringAlgorithm::Greedy6)),
+                other => return Err(format!("unknown coloring algorithm: {}", other)),
+            }
+            write_world(&mut world, args)?;
+        },
+        "neighbors" => {
+            write_world(&mut world, args)?;
+        },
+        "subdivide" => {
+            let id: u32 = args.parse_required("id")?;
+            let mut options = GeneratorOptions::new();
+            options.average_radius = args.parse_or("radius", options.average_radius)?;
+            options.scale_x = args.parse_or("scale-x", options.scale_x)?;
+            options.scale_y = args.parse_or("scale-y", options.scale_y)?;
+            options.random_seed = Some(args.parse_or("seed", 0)?);
+            options.iterations_smooth_center = args.parse_or("smooth-centers", options.iterations_smooth_center)?;
+            options.iterations_smooth_corner = args.parse_or("smooth-corners", options.iterations_smooth_corner)?;
+            options.delete_smaller_than = args.parse_or("delete-smaller-than", options.delete_smaller_than)?;
+            options.merge_smaller_than = args.parse_or("merge-smaller-than", options.merge_smaller_than)?;
+            let new_ids = world.subdivide_into_random_territories(id, &options)
+                .ok_or_else(|| format!("territory {} does not exist", id))?;
+            eprintln!("created {} territories: {:?}", new_ids.len(), new_ids);
+            world.update_neighbors();
+            world.generate_colors_stable();
+            write_world(&mut world, args)?;
+        },
+        "merge-small" => {
+            let threshold: u32 = args.parse_required("threshold")?;
+            let strategy = match args.get("strategy").unwrap_or("smallest") {
+                "smallest" => MergeStrategy::SmallestNeighbor,
+                "border" => MergeStrategy::LongestBorder,
+                "compact" => MergeStrategy::MostCompact,
+                other => return Err(format!("unknown merge strategy: {}", other)),
+            };
+            let removed = world.merge_small_territories(threshold, Some(strategy), None);
+            eprintln!("merged {} territories: {:?}", removed.len(), removed);
+            world.update_neighbors();
+            world.generate_colors_stable();
+            write_world(&mut world, args)?;
+        },
+        "stats" => {
+            let ids = match args.get("ids") {
+                Some(ids) => Some(ids.split(',')
+                    .map(|id| id.trim().parse::<u32>().map_err(|_| format!("invalid territory id: {}", id)))
+                    .collect::<Result<Vec<u32>, String>>()?),
+                None => None,
+            };
+            write_output(args.get("output"), world.territory_stats_batch(ids).as_bytes())?;
+        },
+        "export" => {
+            let format = args.get("format").ok_or_else(|| "missing required option --format".to_string())?;
+            let data = match format {
+                "graph-json" => world.get_adjacency_graph_json().into_bytes(),
+                "graph-dot" => world.get_adjacency_graph_dot().into_bytes(),
+                "snapshot" => {
+                    if args.get("output").is_none() {
+                        return Err("snapshot export requires --output".to_string());
+                    }
+                    world.to_bytes()
+                },
+                "world-json" => world.to_world_json().into_bytes(),
+                other => return Err(format!("unknown export format: {}", other)),
+            };
+            write_output(args.get("output"), &data)?;
+        },
+        _ => return Err(format!("unknown command: {}", command)),
+    }
+
+    Ok(0)
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args[0] == "help" || args[0] == "-h" || args[0] == "--help" {
+        eprint!("{}", USAGE);
+        process::exit(if args.is_empty() { 2 } else { 0 });
+    }
+
+    set_log_hook(Some(stderr_log_hook), LogLevel::Info);
+
+    let command = args.remove(0);
+    let result = Args::parse(args).and_then(|args| run(&command, &args));
+    match result {
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            eprintln!("run `nodes-world help` for usage");
+            process::exit(2);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_WORLD_JSON: &str = include_str!("../../test/nodes/world.json");
+
+    // temp file path unique to this process and test
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("nodes-world-test-{}-{}", process::id(), name));
+        path.to_string_lossy().into_owned()
+    }
+
+    // run command on test world with options, returns exit code and output
+    fn run_with(command: &str, name: &str, options: &[&str]) -> Result<(i32, Vec<u8>), String> {
+        let input = temp_path(&format!("{}-input.json", name));
+        let output = temp_path(&format!("{}-output", name));
+        std::fs::write(&input, TEST_WORLD_JSON).unwrap();
+
+        let mut args = vec![input.clone(), "-o".to_string(), output.clone()];
+        args.extend(options.iter().map(|s| s.to_string()));
+        let result = Args::parse(args).and_then(|args| run(command, &args));
+        let data = std::fs::read(&output).unwrap_or_default();
+
+        let _ = std::fs::remove_file(&input);
+        let _ = std::fs::remove_file(&output);
+        result.map(|code| (code, data))
+    }
+
+    #[test]
+    fn edit_commands_write_world_json() {
+        let commands: [(&str, &[&str]); 3] = [
+            ("recolor", &["--algorithm", "planar5"]),
+            ("neighbors", &[]),
+            ("merge-small", &["--threshold", "4", "--strategy", "compact"]),
+        ];
+        for (command, options) in commands.iter() {
+            let (code, data) = run_with(command, command, options).unwrap();
+            assert_eq!(code, 0, "{}", command);
+            let json = String::from_utf8(data).unwrap();
+            assert!(World::from_world_json(&json, 16).is_ok(), "{}", command);
+        }
+    }
+
+    #[test]
+    fn report_commands_write_output() {
+        let (code, data) = run_with("validate", "validate", &[]).unwrap();
+        assert!(code == 0 || code == 1);
+        assert!(serde_json::from_slice::<serde_json::Value>(&data).unwrap().is_array());
+
+        let (_, data) = run_with("stats", "stats", &[]).unwrap();
+        let stats: serde_json::Value = serde_json::from_slice(&data).unwrap();
+        assert!(stats.as_array().is_some_and(|stats| !stats.is_empty()));
+
+        let (_, data) = run_with("export", "export", &["--format", "snapshot"]).unwrap();
+        assert!(World::from_bytes(&data).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert!(run_with("unknown", "unknown", &[]).is_err());
+        assert!(run_with("subdivide", "subdivide", &[]).is_err());
+        assert!(run_with("merge-small", "merge-small-invalid", &["--threshold", "x"]).is_err());
+        assert!(run_with("export", "export-invalid", &["--format", "png"]).is_err());
+        assert!(Args::parse(vec!["world.json".to_string(), "--id".to_string()]).is_err());
+        assert!(run("validate", &Args::parse(vec![temp_path("missing.json")]).unwrap()).is_err());
+    }
+}
@@ -2,19 +2,25 @@ extern crate num_traits;
 extern crate cfg_if;
 extern crate fnv;
 extern crate rand;
+extern crate serde_json;
 extern crate voronator;
+#[cfg(feature = "wasm")]
 extern crate wasm_bindgen;
 
 #[macro_use]
 extern crate bitflags;
 
+#[allow(unused_imports)]
 #[macro_use]
 extern crate thiserror;
 
-mod territory;
+pub mod territory;
+
+// wasm bindings glue for the browser editor
+#[cfg(feature = "wasm")]
+mod wasm;
 
 use cfg_if::cfg_if;
-use wasm_bindgen::prelude::*;
 
 cfg_if! {
     // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global allocator
@@ -24,15 +30,3 @@ cfg_if! {
         static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
     }
 }
-
-#[wasm_bindgen]
-extern {
-    #[wasm_bindgen(js_namespace = console)]
-    fn log(s: &str);
-}
-
-// test function
-#[wasm_bindgen]
-pub fn greet() {
-    log("hello world!");
-}
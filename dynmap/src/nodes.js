@@ -25,7 +25,7 @@ import { WorldRenderer } from "world/world.jsx";
 import { Territory } from "world/territory.jsx";
 import { Port, PortTooltip } from "world/port.jsx";
 
-import { World, IndexSampler } from "wasm_main";
+import { World, IndexSampler, GeneratorOptions } from "wasm_main";
 
 /**
  * Required format properties for nodes resources.
@@ -938,6 +938,9 @@ const Nodes = {
                     Nodes.wasmWorld.createTerritory(id);
                     Nodes.wasmWorld.removeCoords(terr.chunks);
                     Nodes.wasmWorld.addCoordsToTerritory(id, terr.chunks);
+                    if ( terr.color !== undefined && terr.color !== null ) {
+                        Nodes.wasmWorld.setTerritoryColor(id, terr.color);
+                    }
 
                     // set name
                     newTerritory.name = terr.name;
@@ -950,6 +953,9 @@ const Nodes = {
                     newTerritory.nodes = terr.nodes;
                 });
 
+                // loading a file is not an undoable edit
+                Nodes.wasmWorld.clearHistory();
+
                 // force re-calculate size + border for all territories
                 for ( const [id, terr] of Nodes.territories.entries() ) {
                     terr.size = Nodes.wasmWorld.getTerritorySize(id);
@@ -1104,11 +1110,11 @@ const Nodes = {
 
     // serialize world into a json object
     save: () => {
-        // pre-calculations for on-save data
-        Nodes.wasmWorld.calculateNeighbors();
+        // pre-calculations for on-save data (only territories edited since last update)
+        Nodes.wasmWorld.updateNeighbors();
         
-        // generate territory colors
-        Nodes.wasmWorld.generateColors();
+        // generate territory colors (keeps colors of unchanged territories)
+        Nodes.wasmWorld.generateColorsStable();
 
         // serialize resource nodes
         let nodes = {};
@@ -2816,17 +2822,17 @@ const Nodes = {
             return;
         }
 
-        let newIds = Nodes.wasmWorld.subdivideIntoRandomTerritories(
-            id,
-            Math.max(0, averageRadius),
-            Math.max(0, scaleX),
-            Math.max(0, scaleY),
-            randomSeed,
-            Math.max(0, iterationsSmoothCenters),
-            Math.max(0, iterationsSmoothCorners),
-            Math.max(0, deleteSmallerThan),
-            Math.max(0, mergeSmallerThan),
-        );
+        const options = new GeneratorOptions();
+        options.average_radius = Math.max(0, averageRadius);
+        options.scale_x = Math.max(0, scaleX);
+        options.scale_y = Math.max(0, scaleY);
+        options.random_seed = randomSeed;
+        options.iterations_smooth_center = Math.max(0, iterationsSmoothCenters);
+        options.iterations_smooth_corner = Math.max(0, iterationsSmoothCorners);
+        options.delete_smaller_than = Math.max(0, deleteSmallerThan);
+        options.merge_smaller_than = Math.max(0, mergeSmallerThan);
+        let newIds = Nodes.wasmWorld.subdivideIntoRandomTerritories(id, options);
+        options.free();
 
         // delete old territory
         const oldName = Nodes.territories.get(id).name;
@@ -3619,7 +3625,7 @@ const Nodes = {
     
         // calculate neighbors
         console.log("calculating neighbors");
-        Nodes.wasmWorld.calculateNeighbors();
+        Nodes.wasmWorld.updateNeighbors();
 
         // generate colors
         console.log("generating colors")
@@ -3661,12 +3667,23 @@ const Nodes = {
         Nodes.renderWorld();
     },
 
+    // internal debug function for listing world validation issues
+    // (empty/disconnected territories, holes, diagonal joins, ...)
+    _validate: () => {
+        const issues = JSON.parse(Nodes.wasmWorld.validate());
+        issues.forEach(issue => {
+            console.warn(`${issue.kind}: territories [${issue.territories}], chunks [${issue.chunks}]`);
+        });
+        console.log(`${issues.length} validation issues`);
+        return issues;
+    },
+
     // private internal test fun, calculate borders
     // and set territories isEdge property
     _calculateTerritoriesAtEdge: () => {
         // calculate neighbors
         console.log("calculating neighbors");
-        Nodes.wasmWorld.calculateNeighbors();
+        Nodes.wasmWorld.updateNeighbors();
 
         // set isEdge
         Nodes.territories.forEach(terr => {
@@ -3702,6 +3719,21 @@ const Nodes = {
         return terrIds;
     },
 
+    // internal debug function for chunk ownership raster of region,
+    // downsampled by n (majority owner of n x n chunks). Raster values
+    // are NOT territory ids: value = territory id + 1, 0 = unclaimed.
+    // Throws if raster is too large (more than 2^24 values) or n = 0.
+    _getOwnershipRaster: (xmin, zmin, xmax, zmax, n = 1) => {
+        const owners = Nodes.wasmWorld.getOwnershipRasterDownsampled(xmin, zmin, xmax, zmax, n);
+        const width = Math.ceil((xmax - xmin + 1) / n);
+        const height = Math.ceil((zmax - zmin + 1) / n);
+        const territoryAt = (x, z) => {
+            const value = owners[z * width + x];
+            return value === 0 ? undefined : value - 1;
+        };
+        return { width, height, owners, territoryAt };
+    },
+
     // =====================================
     // town functions
     // =====================================
@@ -0,0 +1,162 @@
+//! adjacency.rs
+//! ----------------------------------------------------------------
+//! Incremental territory adjacency tracking.
+//!
+//! For every pair of territories, counts the number of chunk edges
+//! they share (4-neighborhood, same as `get_neighboring_points`),
+//! and for every territory, counts chunk edges bordering unclaimed
+//! chunks. Two territories are neighbors if they share > 0 edges,
+//! a territory is at the edge if it has > 0 unclaimed edges.
+//!
+//! Counts are updated in O(1) per chunk ownership change by looking
+//! at the 4 neighbors of the changed chunk. Territories whose counts
+//! changed are marked dirty, so only those need their `neighbors`
+//! and `is_at_edge` fields refreshed.
+
+use fnv::{FnvHashMap, FnvHashSet};
+use territory::geometry::Point;
+use territory::territory::NEIGHBORS_4;
+
+pub struct Adjacency {
+    // territory id -> (neighbor id -> shared border length in chunk edges)
+    borders: FnvHashMap<u32, FnvHashMap<u32, u32>>,
+    // territory id -> chunk edges bordering unclaimed chunks
+    edges: FnvHashMap<u32, u32>,
+    // territories whose adjacency changed since last refresh
+    dirty: FnvHashSet<u32>,
+}
+
+impl Default for Adjacency {
+    fn default() -> Adjacency {
+        Adjacency::new()
+    }
+}
+
+impl Adjacency {
+    pub fn new() -> Adjacency {
+        Adjacency {
+            borders: FnvHashMap::default(),
+            edges: FnvHashMap::default(),
+            dirty: FnvHashSet::default(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.borders.clear();
+        self.edges.clear();
+        self.dirty.clear();
+    }
+
+    fn add_border(&mut self, a: u32, b: u32, delta: i32) {
+        for &(x, y) in [(a, b), (b, a)].iter() {
+            let neighbors = self.borders.entry(x).or_default();
+            let count = neighbors.entry(y).or_insert(0);
+            *count = (*count as i32 + delta) as u32;
+            if *count == 0 {
+                neighbors.remove(&y);
+                if neighbors.is_empty() {
+                    self.borders.remove(&x);
+                }
+            }
+        }
+    }
+
+    fn add_edge(&mut self, a: u32, delta: i32) {
+        let count = self.edges.entry(a).or_insert(0);
+        *count = (*count as i32 + delta) as u32;
+        if *count == 0 {
+            self.edges.remove(&a);
+        }
+    }
+
+    // add (delta = 1) or remove (delta = -1) the edge contribution
+    // between chunk owned by `owner` and its neighbor owned by `other`
+    fn add_contribution(&mut self, owner: Option<u32>, other: Option<u32>, delta: i32) {
+        match (owner, other) {
+            (Some(a), Some(b)) => {
+                if a != b {
+                    self.add_border(a, b, delta);
+                }
+            },
+            (Some(a), None) => self.add_edge(a, delta),
+            (None, Some(b)) => self.add_edge(b, delta),
+            (None, None) => (),
+        }
+    }
+
+    /// Update counts after chunk p changed owner from `prev` to `next`.
+    /// `grid` is the world grid (only neighbors of p are read, so it
+    /// does not matter if p itself is already updated).
+    pub fn update_chunk(&mut self, p: Point<i32>, prev: Option<u32>, next: Option<u32>, grid: &FnvHashMap<Point<i32>, u32>) {
+        if prev == next {
+            return;
+        }
+
+        for (dx, dy) in NEIGHBORS_4.iter() {
+            let q = Point::new(p.x + dx, p.y + dy);
+            let other = grid.get(&q).cloned();
+            self.add_contribution(prev, other, -1);
+            self.add_contribution(next, other, 1);
+            if let Some(id) = other {
+                self.dirty.insert(id);
+            }
+        }
+
+        if let Some(id) = prev {
+            self.dirty.insert(id);
+        }
+        if let Some(id) = next {
+            self.dirty.insert(id);
+        }
+    }
+
+    /// Rebuild all counts from world grid. Marks all territories dirty.
+    pub fn rebuild(&mut self, grid: &FnvHashMap<Point<i32>, u32>) {
+        self.clear();
+        for (p, id) in grid.iter() {
+            self.dirty.insert(*id);
+            for (dx, dy) in NEIGHBORS_4.iter() {
+                let q = Point::new(p.x + dx, p.y + dy);
+                match grid.get(&q) {
+                    // count each shared edge once, from its smaller chunk
+                    Some(other) => if *other != *id && (q.x, q.y) > (p.x, p.y) {
+                        self.add_border(*id, *other, 1);
+                    },
+                    None => self.add_edge(*id, 1),
+                }
+            }
+        }
+    }
+
+    /// Take set of territories whose adjacency changed since last call.
+    pub fn take_dirty(&mut self) -> FnvHashSet<u32> {
+        std::mem::take(&mut self.dirty)
+    }
+
+    pub fn neighbors(&self, id: u32) -> FnvHashSet<u32> {
+        match self.borders.get(&id) {
+            Some(neighbors) => neighbors.keys().cloned().collect(),
+            None => FnvHashSet::default(),
+        }
+    }
+
+    // neighbor id -> shared border length in chunk edges
+    pub fn borders(&self, id: u32) -> Option<&FnvHashMap<u32, u32>> {
+        self.borders.get(&id)
+    }
+
+    // total border length of territory (shared + unclaimed edges)
+    pub fn perimeter(&self, id: u32) -> u32 {
+        let shared: u32 = self.borders.get(&id).map_or(0, |n| n.values().sum());
+        shared + self.edge_length(id)
+    }
+
+    // chunk edges of territory bordering unclaimed chunks
+    pub fn edge_length(&self, id: u32) -> u32 {
+        self.edges.get(&id).cloned().unwrap_or(0)
+    }
+
+    pub fn is_at_edge(&self, id: u32) -> bool {
+        self.edge_length(id) > 0
+    }
+}
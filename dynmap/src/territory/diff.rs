@@ -0,0 +1,410 @@
+//! diff.rs
+//! ----------------------------------------------------------------
+//! Ownership changeset between two worlds.
+//!
+//! A diff from world `old` to world `new` lists territories created
+//! (ids only in new, with their color and meta such as name) and
+//! deleted (ids only in old), every chunk whose owner changed, and the
+//! resulting changes in territory neighbors. Neighbor changes are
+//! informational: applying the chunk changes reproduces them.
+//!
+//! json format:
+//! {
+//!     "created": [{ "id": id, "color": color or null, "meta": { "name": name, ... } }, ...],
+//!     "deleted": [id, ...],
+//!     "chunks": [[x, y, prev id or null, next id or null], ...],
+//!     "neighbors": [{ "id": id, "added": [id, ...], "removed": [id, ...] }, ...]
+//! }
+
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+use std::convert::TryFrom;
+use fnv::{FnvHashMap, FnvHashSet};
+use serde_json::{Map, Value};
+use territory::adjacency::Adjacency;
+use territory::geometry::Point;
+use territory::territory::Territory;
+use territory::world::{WorldError, encode_owner};
+
+/// Chunk owner change, None = unclaimed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkChange {
+    pub p: Point<i32>,
+    pub prev: Option<u32>,
+    pub next: Option<u32>,
+}
+
+/// Territory created by diff, without chunks
+#[derive(Clone, Debug, PartialEq)]
+pub struct CreatedTerritory {
+    pub id: u32,
+    pub color: Option<u8>,
+    pub meta: Map<String, Value>,
+}
+
+/// Neighbors added/removed for a territory
+#[derive(Clone, Debug, PartialEq)]
+pub struct NeighborChange {
+    pub id: u32,
+    pub added: Vec<u32>,
+    pub removed: Vec<u32>,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorldDiff {
+    // sorted by id
+    created: Vec<CreatedTerritory>,
+    deleted: Vec<u32>,
+    chunks: Vec<ChunkChange>,
+    neighbors: Vec<NeighborChange>,
+}
+
+fn invalid(msg: &str) -> WorldError {
+    WorldError::InvalidJson(format!("world diff: {}", msg))
+}
+
+fn id_to_json(id: Option<u32>) -> Value {
+    id.map_or(Value::Null, Value::from)
+}
+
+fn id_from_json(value: &Value) -> Result<Option<u32>, WorldError> {
+    match value {
+        Value::Null => Ok(None),
+        _ => value.as_u64()
+            .and_then(|id| u32::try_from(id).ok())
+            .map(Some)
+            .ok_or_else(|| invalid("invalid territory id")),
+    }
+}
+
+fn ids_from_json(value: Option<&Value>) -> Result<Vec<u32>, WorldError> {
+    match value {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::Array(values)) => values.iter()
+            .map(|v| id_from_json(v)?.ok_or_else(|| invalid("invalid territory id")))
+            .collect(),
+        Some(_) => Err(invalid("expected array of ids")),
+    }
+}
+
+// created territory object, always with color and meta
+fn created_to_json(terr: &CreatedTerritory) -> Value {
+    let mut obj = Map::new();
+    obj.insert("id".to_string(), Value::from(terr.id));
+    obj.insert("color".to_string(), terr.color.map_or(Value::Null, Value::from));
+    obj.insert("meta".to_string(), Value::Object(terr.meta.clone()));
+    Value::Object(obj)
+}
+
+fn created_from_json(value: &Value) -> Result<CreatedTerritory, WorldError> {
+    if value.is_number() {
+        let id = id_from_json(value)?.ok_or_else(|| invalid("invalid territory id"))?;
+        return Ok(CreatedTerritory { id, color: None, meta: Map::new() });
+    }
+
+    let obj = value.as_object().ok_or_else(|| invalid("invalid created territory"))?;
+    let id = id_from_json(obj.get("id").unwrap_or(&Value::Null))?
+        .ok_or_else(|| invalid("invalid created territory id"))?;
+    let color = match obj.get("color") {
+        None | Some(Value::Null) => None,
+        Some(v) => Some(v.as_u64()
+            .and_then(|c| u8::try_from(c).ok())
+            .ok_or_else(|| invalid("invalid created territory color"))?),
+    };
+    let meta = match obj.get("meta") {
+        None | Some(Value::Null) => Map::new(),
+        Some(Value::Object(meta)) => meta.clone(),
+        Some(_) => return Err(invalid("invalid created territory meta")),
+    };
+    Ok(CreatedTerritory { id, color, meta })
+}
+
+fn sorted(ids: FnvHashSet<u32>) -> Vec<u32> {
+    let mut ids: Vec<u32> = ids.into_iter().collect();
+    ids.sort();
+    ids
+}
+
+impl WorldDiff {
+    pub fn created(&self) -> &[CreatedTerritory] {
+        &self.created
+    }
+
+    pub fn created_ids(&self) -> Vec<u32> {
+        self.created.iter().map(|terr| terr.id).collect()
+    }
+
+    pub fn deleted(&self) -> &[u32] {
+        &self.deleted
+    }
+
+    pub fn chunks(&self) -> &[ChunkChange] {
+        &self.chunks
+    }
+
+    pub fn to_json(&self) -> Value {
+        let chunks: Vec<Value> = self.chunks.iter()
+            .map(|c| Value::Array(vec![
+                Value::from(c.p.x),
+                Value::from(c.p.y),
+                id_to_json(c.prev),
+                id_to_json(c.next),
+            ]))
+            .collect();
+
+        let neighbors: Vec<Value> = self.neighbors.iter()
+            .map(|n| {
+                let mut obj = Map::new();
+                obj.insert("id".to_string(), Value::from(n.id));
+                obj.insert("added".to_string(), Value::from(n.added.clone()));
+                obj.insert("removed".to_string(), Value::from(n.removed.clone()));
+                Value::Object(obj)
+            })
+            .collect();
+
+        let mut obj = Map::new();
+        obj.insert("created".to_string(), Value::Array(self.created.iter().map(created_to_json).collect()));
+        obj.insert("deleted".to_string(), Value::from(self.deleted.clone()));
+        obj.insert("chunks".to_string(), Value::Array(chunks));
+        obj.insert("neighbors".to_string(), Value::Array(neighbors));
+        Value::Object(obj)
+    }
+
+    pub fn from_json(value: &Value) -> Result<WorldDiff, WorldError> {
+        let obj = value.as_object().ok_or_else(|| invalid("expected json object"))?;
+
+        let mut chunks: Vec<ChunkChange> = Vec::new();
+        match obj.get("chunks") {
+            None | Some(Value::Null) => (),
+            Some(Value::Array(values)) => {
+                for v in values.iter() {
+                    let c = match v.as_array() {
+                        Some(c) if c.len() == 4 => c,
+                        _ => return Err(invalid("expected chunk change [x, y, prev, next]")),
+                    };
+                    let coord = |v: &Value| v.as_i64()
+                        .and_then(|x| i32::try_from(x).ok())
+                        .ok_or_else(|| invalid("invalid chunk coord"));
+                    chunks.push(ChunkChange {
+                        p: Point::new(coord(&c[0])?, coord(&c[1])?),
+                        prev: id_from_json(&c[2])?,
+                        next: id_from_json(&c[3])?,
+                    });
+                }
+            },
+            Some(_) => return Err(invalid("invalid \"chunks\"")),
+        }
+
+        let mut neighbors: Vec<NeighborChange> = Vec::new();
+        match obj.get("neighbors") {
+            None | Some(Value::Null) => (),
+            Some(Value::Array(values)) => {
+                for v in values.iter() {
+                    let id = id_from_json(v.get("id").unwrap_or(&Value::Null))?
+                        .ok_or_else(|| invalid("invalid neighbor change id"))?;
+                    neighbors.push(NeighborChange {
+                        id,
+                        added: ids_from_json(v.get("added"))?,
+                        removed: ids_from_json(v.get("removed"))?,
+                    });
+                }
+            },
+            Some(_) => return Err(invalid("invalid \"neighbors\"")),
+        }
+
+        let mut created: Vec<CreatedTerritory> = match obj.get("created") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Array(values)) => values.iter().map(created_from_json).collect::<Result<_, _>>()?,
+            Some(_) => return Err(invalid("invalid \"created\"")),
+        };
+        created.sort_by_key(|terr| terr.id);
+
+        Ok(WorldDiff {
+            created,
+            deleted: ids_from_json(obj.get("deleted"))?,
+            chunks,
+            neighbors,
+        })
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl WorldDiff {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=fromJson))]
+    pub fn from_json_str(json: &str) -> Result<WorldDiff, WorldError> {
+        let value: Value = serde_json::from_str(json).map_err(|err| invalid(&err.to_string()))?;
+        WorldDiff::from_json(&value)
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=toJson))]
+    pub fn to_json_str(&self) -> String {
+        self.to_json().to_string()
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=isEmpty))]
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.deleted.is_empty() && self.chunks.is_empty()
+    }
+
+    /// Created territory ids, sorted
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getCreated))]
+    pub fn get_created(&self) -> Vec<u32> {
+        self.created_ids()
+    }
+
+    /// Deleted territory ids, sorted
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getDeleted))]
+    pub fn get_deleted(&self) -> Vec<u32> {
+        self.deleted.clone()
+    }
+
+    /// Changed chunks buffer [x1, y1, prev1, next1, x2, y2, ...],
+    /// owners in `encode_owner` format (territory id + 1, 0 = unclaimed),
+    /// coords are i32 bit patterns (read with `| 0` or an Int32Array)
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getChunks))]
+    pub fn get_chunks(&self) -> Vec<u32> {
+        self.chunks.iter()
+            .flat_map(|c| vec![c.p.x as u32, c.p.y as u32, encode_owner(c.prev), encode_owner(c.next)])
+            .collect()
+    }
+}
+
+/// Diff world `old` -> `new` from their grids, territories and
+/// adjacency (neighbors from adjacency, so territory neighbors do not
+/// need to be updated).
+pub fn compute_diff(
+    old_grid: &FnvHashMap<Point<i32>, u32>,
+    old_territories: &FnvHashMap<u32, Territory>,
+    old_adjacency: &Adjacency,
+    new_grid: &FnvHashMap<Point<i32>, u32>,
+    new_territories: &FnvHashMap<u32, Territory>,
+    new_adjacency: &Adjacency,
+) -> WorldDiff {
+    let mut created: Vec<CreatedTerritory> = new_territories.values()
+        .filter(|terr| !old_territories.contains_key(&terr.id))
+        .map(|terr| CreatedTerritory { id: terr.id, color: terr.color, meta: terr.meta.clone() })
+        .collect();
+    created.sort_by_key(|terr| terr.id);
+    let deleted: FnvHashSet<u32> = old_territories.keys().filter(|id| !new_territories.contains_key(id)).cloned().collect();
+
+    let mut chunks: Vec<ChunkChange> = Vec::new();
+    for (p, id) in old_grid.iter() {
+        let next = new_grid.get(p).cloned();
+        if next != Some(*id) {
+            chunks.push(ChunkChange { p: *p, prev: Some(*id), next });
+        }
+    }
+    for (p, id) in new_grid.iter() {
+        if !old_grid.contains_key(p) {
+            chunks.push(ChunkChange { p: *p, prev: None, next: Some(*id) });
+        }
+    }
+    chunks.sort_by_key(|c| (c.p.x, c.p.y));
+
+    // neighbor changes of all territories in either world
+    let mut ids: FnvHashSet<u32> = old_territories.keys().cloned().collect();
+    ids.extend(new_territories.keys().cloned());
+    let mut neighbors: Vec<NeighborChange> = Vec::new();
+    for id in sorted(ids).into_iter() {
+        let old_neighbors = old_adjacency.neighbors(id);
+        let new_neighbors = new_adjacency.neighbors(id);
+        if old_neighbors != new_neighbors {
+            neighbors.push(NeighborChange {
+                id,
+                added: sorted(new_neighbors.difference(&old_neighbors).cloned().collect()),
+                removed: sorted(old_neighbors.difference(&new_neighbors).cloned().collect()),
+            });
+        }
+    }
+
+    WorldDiff {
+        created,
+        deleted: sorted(deleted),
+        chunks,
+        neighbors,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use territory::world::World;
+
+    fn world(territories: &[(u32, &[i32])]) -> World {
+        let mut world = World::new(16).unwrap();
+        for (id, coords) in territories.iter() {
+            world.create_territory(Some(*id)).unwrap();
+            world.add_coords_to_territory(*id, coords.to_vec());
+        }
+        world
+    }
+
+    #[test]
+    fn compute_diff_lists_changes() {
+        let old = world(&[(1, &[0, 0, 1, 0]), (2, &[2, 0]), (3, &[5, 5])]);
+        let mut new = world(&[(1, &[0, 0]), (2, &[2, 0, 1, 0]), (4, &[3, 0])]);
+        new.set_territory_color(4, Some(2));
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.created_ids(), vec![4]);
+        assert_eq!(diff.created()[0].color, Some(2));
+        assert_eq!(diff.deleted(), &[3]);
+        assert_eq!(diff.get_chunks(), vec![1, 0, 2, 3, 3, 0, 0, 5, 5, 5, 4, 0]);
+
+        // 1 lost neighbor 2 (chunk moved), 2 gained 4
+        let neighbors: FnvHashMap<u32, &NeighborChange> = diff.neighbors.iter().map(|n| (n.id, n)).collect();
+        assert_eq!(neighbors[&2].added, vec![4]);
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn chunks_buffer_encodes_owners() {
+        let old = world(&[(u32::MAX - 1, &[-2, 3])]);
+        let new = world(&[(0, &[-2, 3])]);
+
+        // coords keep i32 bits, owner ids above i32 max stay distinct
+        let chunks = old.diff(&new).get_chunks();
+        assert_eq!(chunks, vec![-2i32 as u32, 3, u32::MAX, 1]);
+        assert_eq!(chunks[0] as i32, -2);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let old = world(&[(1, &[0, 0, 1, 0])]);
+        let mut new = world(&[(1, &[0, 0]), (7, &[1, 0, -3, 1_000_000])]);
+        new.set_territory_color(7, Some(255));
+
+        let diff = old.diff(&new);
+        let json = diff.to_json_str();
+        assert_eq!(WorldDiff::from_json_str(&json).unwrap(), diff);
+    }
+
+    #[test]
+    fn from_json_accepts_bare_created_ids() {
+        let diff = WorldDiff::from_json_str(r#"{"created": [5, {"id": 3, "color": 1, "meta": {"name": "a"}}], "chunks": [[0, 0, null, 5]]}"#).unwrap();
+        assert_eq!(diff.created_ids(), vec![3, 5]);
+        assert_eq!(diff.created()[0].color, Some(1));
+        assert_eq!(diff.created()[0].meta.get("name"), Some(&Value::from("a")));
+        assert_eq!(diff.created()[1], CreatedTerritory { id: 5, color: None, meta: Map::new() });
+        assert!(diff.deleted().is_empty());
+    }
+
+    #[test]
+    fn from_json_rejects_invalid() {
+        let invalid = [
+            "[]",
+            r#"{"created": [-1]}"#,
+            r#"{"created": [4294967296]}"#,
+            r#"{"created": [{"id": 1, "color": 256}]}"#,
+            r#"{"created": [{"id": 1, "meta": []}]}"#,
+            r#"{"deleted": [1.5]}"#,
+            r#"{"chunks": [[0, 0, null]]}"#,
+            r#"{"chunks": [[2147483648, 0, null, 1]]}"#,
+        ];
+        for json in invalid.iter() {
+            assert!(WorldDiff::from_json_str(json).is_err(), "{}", json);
+        }
+    }
+}
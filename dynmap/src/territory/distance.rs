@@ -0,0 +1,111 @@
+//! distance.rs
+//! ----------------------------------------------------------------
+//! Chunk distance fields from a set of source territories.
+//!
+//! Multi-source BFS over the 4-neighborhood starting from all chunks
+//! of the source territories (distance 0). Distances are in chunk
+//! steps, search stops at `max_distance`. Search can be restricted to
+//! claimed chunks only (distance through territories) or run over all
+//! chunks (unclaimed chunks are included in the output). Unclaimed
+//! space is unbounded, so searches over all chunks are limited to
+//! `MAX_UNCLAIMED_DISTANCE`.
+
+
+use std::collections::VecDeque;
+use std::collections::hash_map::Entry;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+use fnv::{FnvHashMap};
+use territory::geometry::Point;
+use territory::territory::{Territory, NEIGHBORS_4};
+
+/// Max distance of searches including unclaimed chunks, visited chunks
+/// grow with source perimeter * distance + distance^2
+pub const MAX_UNCLAIMED_DISTANCE: u32 = 256;
+
+/// Result of `World::distance_field`
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub struct DistanceField {
+    // [x1, y1, d1, x2, y2, d2, ...] sorted by distance, then x, y
+    chunks: Vec<i32>,
+    // [id1, min1, max1, id2, min2, max2, ...] sorted by id
+    territories: Vec<u32>,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DistanceField {
+    /// Chunk distances buffer [x1, y1, d1, x2, y2, d2, ...]
+    /// sorted by distance, then chunk x, y.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getChunks))]
+    pub fn get_chunks(&self) -> Vec<i32> {
+        self.chunks.clone()
+    }
+
+    /// Min/max distance of reached chunks in each territory,
+    /// buffer [id1, min1, max1, id2, min2, max2, ...] sorted by id.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritories))]
+    pub fn get_territories(&self) -> Vec<u32> {
+        self.territories.clone()
+    }
+}
+
+/// Run BFS from chunks of source territories, up to max_distance
+/// (inclusive). If `claimed_only`, only claimed chunks are visited,
+/// otherwise caller must limit max_distance (`MAX_UNCLAIMED_DISTANCE`).
+pub fn distance_field(
+    grid: &FnvHashMap<Point<i32>, u32>,
+    territories: &FnvHashMap<u32, Territory>,
+    source_ids: &[u32],
+    max_distance: u32,
+    claimed_only: bool,
+) -> DistanceField {
+    let mut distances: FnvHashMap<Point<i32>, u32> = FnvHashMap::default();
+    let mut queue: VecDeque<Point<i32>> = VecDeque::new();
+
+    for id in source_ids.iter() {
+        if let Some(terr) = territories.get(id) {
+            for p in terr.coords.iter() {
+                if distances.insert(*p, 0).is_none() {
+                    queue.push_back(*p);
+                }
+            }
+        }
+    }
+
+    while let Some(p) = queue.pop_front() {
+        let d = distances[&p];
+        if d >= max_distance {
+            continue;
+        }
+        for (dx, dy) in NEIGHBORS_4.iter() {
+            let q = Point::new(p.x + dx, p.y + dy);
+            if claimed_only && !grid.contains_key(&q) {
+                continue;
+            }
+            if let Entry::Vacant(entry) = distances.entry(q) {
+                entry.insert(d + 1);
+                queue.push_back(q);
+            }
+        }
+    }
+
+    let mut chunks: Vec<(u32, i32, i32)> = distances.iter().map(|(p, d)| (*d, p.x, p.y)).collect();
+    chunks.sort();
+
+    // per territory min/max distance
+    let mut ranges: FnvHashMap<u32, (u32, u32)> = FnvHashMap::default();
+    for (d, x, y) in chunks.iter() {
+        if let Some(id) = grid.get(&Point::new(*x, *y)) {
+            let range = ranges.entry(*id).or_insert((*d, *d));
+            range.0 = range.0.min(*d);
+            range.1 = range.1.max(*d);
+        }
+    }
+    let mut ranges: Vec<(u32, (u32, u32))> = ranges.into_iter().collect();
+    ranges.sort();
+
+    DistanceField {
+        chunks: chunks.into_iter().flat_map(|(d, x, y)| vec![x, y, d as i32]).collect(),
+        territories: ranges.into_iter().flat_map(|(id, (min, max))| vec![id, min, max]).collect(),
+    }
+}
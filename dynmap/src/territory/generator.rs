@@ -1,3 +1,5 @@
+#![allow(clippy::empty_line_after_doc_comments, clippy::needless_return, clippy::redundant_field_names)]
+
 /// Functions for random generating territory cells.
 /// Emits CellDiagram which contain smoothed Voronoi-based
 /// randomly generated cells.
@@ -11,6 +13,8 @@
 /// http://www-cs-students.stanford.edu/%7Eamitp/game-programming/polygon-map-generation/
 /// https://en.wikipedia.org/wiki/Lloyd%27s_algorithm
 
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
 use std::hash::{Hash, Hasher};
 use std::collections::HashMap;
 use voronator::{VoronoiDiagram, delaunator::Point as VoronoiPoint};
@@ -20,6 +24,55 @@ use std::mem;
 use territory::geometry::{AABB, Point};
 
 
+/// Random territory generation settings, used when subdividing a
+/// territory or generating territories over unclaimed chunks.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, Debug)]
+pub struct GeneratorOptions {
+    // average territory cell radius in chunks
+    pub average_radius: f64,
+    // cell scale, stretches cells along x/y
+    pub scale_x: f64,
+    pub scale_y: f64,
+    // fixed seed for reproducible cells (None = random)
+    pub random_seed: Option<u32>,
+    // Lloyd relaxation iterations
+    pub iterations_smooth_center: u32,
+    // corner averaging iterations
+    pub iterations_smooth_corner: u32,
+    // drop generated territories smaller than this (0 = disabled)
+    pub delete_smaller_than: u32,
+    // merge generated territories smaller than this into their
+    // smallest generated neighbor (0 = disabled)
+    pub merge_smaller_than: u32,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> GeneratorOptions {
+        return GeneratorOptions::new();
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl GeneratorOptions {
+    /// Default options: radius 4, no scaling, random seed,
+    /// 2 center and corner smoothing iterations, no delete/merge.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new() -> GeneratorOptions {
+        return GeneratorOptions {
+            average_radius: 4.0,
+            scale_x: 1.0,
+            scale_y: 1.0,
+            random_seed: None,
+            iterations_smooth_center: 2,
+            iterations_smooth_corner: 2,
+            delete_smaller_than: 0,
+            merge_smaller_than: 0,
+        };
+    }
+}
+
+#[allow(unknown_lints, unnecessary_transmutes)]
 fn integer_decode(val: f64) -> (u64, i16, i8) {
     let bits: u64 = unsafe { mem::transmute(val) };
     let sign: i8 = if bits >> 63 == 0 { 1 } else { -1 };
@@ -81,6 +134,7 @@ impl Corner {
 
 // triangle winding orientation
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 enum Orientation {
     Collinear,
     CW, // clockwise
@@ -98,6 +152,7 @@ pub struct CellDiagram {
 }
 
 impl CellDiagram {
+    #[allow(clippy::needless_borrow)]
     pub fn from_voronoi_diagram(voronoi: VoronoiDiagram, min: &(f64, f64), max: &(f64, f64)) -> CellDiagram {
         // for detecting if points on border of voronoi diagram range
         const EPS: f64 = 1e-6;
@@ -232,6 +287,7 @@ impl CellDiagram {
     ///    - Even # of hits = outside polygon
     ///    - Odd # of hits = inside polygon
     /// 3. Return first cell that point lies inside, otherwise return None
+    #[allow(clippy::question_mark)]
     pub fn cell_contains_coords(&self, x: f64, y: f64) -> Option<usize> {
         // generic tri orientation detection
         // Returns orientation for point triplet (p, q, r)
@@ -441,6 +497,7 @@ fn smooth_corners(mut cell_diagram: CellDiagram) -> CellDiagram {
 ///    Set points from centroids
 /// 
 /// Repeat until cells become more uniform
+#[allow(clippy::needless_borrow)]
 fn smooth_centers(voronoi: &VoronoiDiagram, min: &(f64, f64), max: &(f64, f64)) -> Option<VoronoiDiagram> {
     let points: Vec<VoronoiPoint> = voronoi.cells().iter()
         .map(|cell| centroid_from_points(&cell.points()))
@@ -457,6 +514,7 @@ fn smooth_centers(voronoi: &VoronoiDiagram, min: &(f64, f64), max: &(f64, f64))
 /// Cells are generated as voronois then smoothed with `iterations_smooth_center`
 /// using Lloyd Relaxation followed by `iterations_smooth_corner` of averaging
 /// the corner points to adjacent cell centroids.
+#[allow(clippy::approx_constant, clippy::needless_borrow, clippy::needless_borrows_for_generic_args)]
 pub fn generate_random_cells(
     average_radius: f64,
     min: &(f64, f64),
@@ -1,3 +1,5 @@
+#![allow(clippy::empty_line_after_doc_comments, clippy::needless_return, clippy::redundant_field_names)]
+
 /// geometry.rs
 /// ----------------------------------------------------------------
 /// 2D geometry primitives
@@ -45,6 +47,7 @@ impl<T> AABB<T> where T: Num + Copy + Bounded + PartialOrd {
     }
     
     // polygon defined by a point set
+    #[allow(clippy::ptr_arg)]
     pub fn from_polygon(polygon: &Vec<Point<T>>) -> AABB<T> {
         
         // get bounding box of coords
@@ -0,0 +1,192 @@
+//! graph.rs
+//! ----------------------------------------------------------------
+//! Territory adjacency graph export and queries.
+//!
+//! Graph vertices are territories, edges connect territories sharing
+//! a border, edge weight is the shared border length in chunk edges
+//! (from `Adjacency`). Outputs are sorted by id so results do not
+//! depend on hash map iteration order.
+
+use std::collections::VecDeque;
+use fnv::{FnvHashMap, FnvHashSet};
+use serde_json::{Map, Value};
+use territory::adjacency::Adjacency;
+
+// sorted neighbor ids of territory
+fn sorted_neighbors(adjacency: &Adjacency, id: u32) -> Vec<u32> {
+    let mut neighbors: Vec<u32> = match adjacency.borders(id) {
+        Some(borders) => borders.keys().cloned().collect(),
+        None => Vec::new(),
+    };
+    neighbors.sort();
+    neighbors
+}
+
+// sorted edges (a, b, weight) with a < b
+fn sorted_edges(adjacency: &Adjacency, ids: &[u32]) -> Vec<(u32, u32, u32)> {
+    let mut edges: Vec<(u32, u32, u32)> = Vec::new();
+    for id in ids.iter() {
+        if let Some(borders) = adjacency.borders(*id) {
+            for (neighbor_id, weight) in borders.iter() {
+                if *id < *neighbor_id {
+                    edges.push((*id, *neighbor_id, *weight));
+                }
+            }
+        }
+    }
+    edges.sort();
+    edges
+}
+
+/// Graph as json:
+/// {
+///     "nodes": [{ "id": id, "size": chunks }, ...],
+///     "edges": [{ "source": id1, "target": id2, "weight": border length }, ...]
+/// }
+/// `sizes` maps territory id -> number of chunks for all territories.
+pub fn graph_to_json(adjacency: &Adjacency, sizes: &FnvHashMap<u32, usize>) -> Value {
+    let mut ids: Vec<u32> = sizes.keys().cloned().collect();
+    ids.sort();
+
+    let nodes: Vec<Value> = ids.iter()
+        .map(|id| {
+            let mut node = Map::new();
+            node.insert("id".to_string(), Value::from(*id));
+            node.insert("size".to_string(), Value::from(sizes[id]));
+            Value::Object(node)
+        })
+        .collect();
+
+    let edges: Vec<Value> = sorted_edges(adjacency, &ids).into_iter()
+        .map(|(a, b, weight)| {
+            let mut edge = Map::new();
+            edge.insert("source".to_string(), Value::from(a));
+            edge.insert("target".to_string(), Value::from(b));
+            edge.insert("weight".to_string(), Value::from(weight));
+            Value::Object(edge)
+        })
+        .collect();
+
+    let mut graph = Map::new();
+    graph.insert("nodes".to_string(), Value::Array(nodes));
+    graph.insert("edges".to_string(), Value::Array(edges));
+    Value::Object(graph)
+}
+
+/// Graph in Graphviz DOT format, undirected graph with
+/// edge weight and label set to shared border length.
+pub fn graph_to_dot(adjacency: &Adjacency, sizes: &FnvHashMap<u32, usize>) -> String {
+    let mut ids: Vec<u32> = sizes.keys().cloned().collect();
+    ids.sort();
+
+    let mut dot = String::from("graph territories {\n");
+    for id in ids.iter() {
+        dot.push_str(&format!("    {} [size={}];\n", id, sizes[id]));
+    }
+    for (a, b, weight) in sorted_edges(adjacency, &ids).into_iter() {
+        dot.push_str(&format!("    {} -- {} [weight={}, label={}];\n", a, b, weight, weight));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Shortest path by number of hops from territory `from` to `to`
+/// (inclusive of both), None if not connected.
+pub fn shortest_path(adjacency: &Adjacency, from: u32, to: u32) -> Option<Vec<u32>> {
+    if from == to {
+        return Some(vec![from]);
+    }
+
+    let mut parent: FnvHashMap<u32, u32> = FnvHashMap::default();
+    let mut queue: VecDeque<u32> = VecDeque::new();
+    parent.insert(from, from);
+    queue.push_back(from);
+
+    while let Some(id) = queue.pop_front() {
+        for neighbor_id in sorted_neighbors(adjacency, id).into_iter() {
+            if parent.contains_key(&neighbor_id) {
+                continue;
+            }
+            parent.insert(neighbor_id, id);
+
+            if neighbor_id == to {
+                // walk back to start
+                let mut path = vec![to];
+                let mut current = to;
+                while current != from {
+                    current = parent[&current];
+                    path.push(current);
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            queue.push_back(neighbor_id);
+        }
+    }
+
+    None
+}
+
+/// Territories within 1..=k hops from territory id (excluding id), sorted.
+pub fn within_hops(adjacency: &Adjacency, id: u32, k: u32) -> Vec<u32> {
+    let mut visited: FnvHashSet<u32> = FnvHashSet::default();
+    let mut frontier: Vec<u32> = vec![id];
+    visited.insert(id);
+
+    for _ in 0..k {
+        let mut next: Vec<u32> = Vec::new();
+        for v in frontier.iter() {
+            for neighbor_id in sorted_neighbors(adjacency, *v).into_iter() {
+                if visited.insert(neighbor_id) {
+                    next.push(neighbor_id);
+                }
+            }
+        }
+        if next.is_empty() {
+            break;
+        }
+        frontier = next;
+    }
+
+    visited.remove(&id);
+    let mut ids: Vec<u32> = visited.into_iter().collect();
+    ids.sort();
+    ids
+}
+
+/// Connected components of the graph induced by a subset of territory
+/// ids (only edges between ids in the subset). Components are sorted
+/// ids, ordered largest first, ties broken by smallest id.
+pub fn subset_components(adjacency: &Adjacency, ids: &FnvHashSet<u32>) -> Vec<Vec<u32>> {
+    let mut sorted_ids: Vec<u32> = ids.iter().cloned().collect();
+    sorted_ids.sort();
+
+    let mut visited: FnvHashSet<u32> = FnvHashSet::default();
+    let mut components: Vec<Vec<u32>> = Vec::new();
+
+    for start in sorted_ids.into_iter() {
+        if !visited.insert(start) {
+            continue;
+        }
+
+        let mut component: Vec<u32> = Vec::new();
+        let mut stack: Vec<u32> = vec![start];
+        while let Some(id) = stack.pop() {
+            component.push(id);
+            if let Some(borders) = adjacency.borders(id) {
+                for neighbor_id in borders.keys() {
+                    if ids.contains(neighbor_id) && visited.insert(*neighbor_id) {
+                        stack.push(*neighbor_id);
+                    }
+                }
+            }
+        }
+
+        component.sort();
+        components.push(component);
+    }
+
+    components.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+    components
+}
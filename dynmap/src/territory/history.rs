@@ -0,0 +1,328 @@
+//! history.rs
+//! ----------------------------------------------------------------
+//! Undo/redo transaction log for World edits.
+//!
+//! Every World mutation is recorded as a sequence of invertible
+//! `Change`s (chunk ownership deltas, created/removed territory ids
+//! and id counter changes). Changes are collected into a `Changeset`
+//! which is committed when the outermost group is closed, so that
+//! one user operation (e.g. subdivide) is undone in a single step.
+//!
+//! Undo applies changeset in reverse order with inverted changes,
+//! redo re-applies changes in forward order. History is bounded by
+//! an approximate memory budget, oldest changesets are dropped first.
+
+use std::collections::VecDeque;
+use std::mem;
+use serde_json::{Map, Value};
+use territory::geometry::Point;
+
+// default history memory budget in bytes
+pub const DEFAULT_HISTORY_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Single invertible world edit
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    // chunk owner changed from `prev` -> `next` (None = unclaimed)
+    Chunk {
+        p: Point<i32>,
+        prev: Option<u32>,
+        next: Option<u32>,
+    },
+    // new territory created with id, color and meta (without chunks)
+    CreateTerritory {
+        id: u32,
+        color: Option<u8>,
+        meta: Box<Map<String, Value>>,
+    },
+    // empty territory removed, stores data needed to re-create it
+    RemoveTerritory {
+        id: u32,
+        color: Option<u8>,
+        meta: Box<Map<String, Value>>,
+    },
+    // territory id counter changed from `prev` -> `next`
+    IdCounter {
+        prev: u32,
+        next: u32,
+    },
+}
+
+/// Group of changes undone/redone together
+#[derive(Clone, Debug, Default)]
+pub struct Changeset {
+    pub changes: Vec<Change>,
+}
+
+impl Changeset {
+    pub fn new() -> Changeset {
+        Changeset {
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    // approximate memory used by changeset in bytes, includes
+    // boxed territory meta of created/removed territories
+    pub fn memory_size(&self) -> usize {
+        let meta_size: usize = self.changes.iter()
+            .map(|change| match change {
+                Change::CreateTerritory { meta, .. } | Change::RemoveTerritory { meta, .. } => map_memory_size(meta),
+                _ => 0,
+            })
+            .sum();
+        mem::size_of::<Changeset>() + self.changes.capacity() * mem::size_of::<Change>() + meta_size
+    }
+}
+
+// approximate heap memory of a json object, keys + values
+fn map_memory_size(map: &Map<String, Value>) -> usize {
+    let entries: usize = map.iter()
+        .map(|(key, value)| mem::size_of::<String>() + key.capacity() + value_memory_size(value))
+        .sum();
+    mem::size_of::<Map<String, Value>>() + entries
+}
+
+fn value_memory_size(value: &Value) -> usize {
+    let heap = match value {
+        Value::String(s) => s.capacity(),
+        Value::Array(values) => values.iter().map(value_memory_size).sum(),
+        Value::Object(map) => map_memory_size(map),
+        _ => 0,
+    };
+    mem::size_of::<Value>() + heap
+}
+
+pub struct History {
+    // committed changesets, oldest at front
+    undo_stack: VecDeque<Changeset>,
+    // undone changesets, most recently undone at back
+    redo_stack: Vec<Changeset>,
+    // changes recorded in currently open group
+    current: Changeset,
+    // nested group depth, changes committed when this returns to 0
+    group_depth: u32,
+    // approximate memory used by undo + redo stacks in bytes
+    memory_used: usize,
+    // max memory for undo + redo stacks in bytes
+    memory_limit: usize,
+}
+
+impl Default for History {
+    fn default() -> History {
+        History::new()
+    }
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            current: Changeset::new(),
+            group_depth: 0,
+            memory_used: 0,
+            memory_limit: DEFAULT_HISTORY_MEMORY_LIMIT,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.current = Changeset::new();
+        self.group_depth = 0;
+        self.memory_used = 0;
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+
+    pub fn set_memory_limit(&mut self, limit: usize) {
+        self.memory_limit = limit;
+        self.enforce_memory_limit();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty() || !self.current.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    // open a (possibly nested) group of changes
+    pub fn begin_group(&mut self) {
+        self.group_depth += 1;
+    }
+
+    // close group, commit changes if this closes the outermost group.
+    // unbalanced calls are ignored.
+    pub fn end_group(&mut self) {
+        if self.group_depth > 0 {
+            self.group_depth -= 1;
+        }
+        if self.group_depth == 0 {
+            self.commit();
+        }
+    }
+
+    // record a change into current group. any new change invalidates redo.
+    pub fn record(&mut self, change: Change) {
+        if !self.redo_stack.is_empty() {
+            for cs in self.redo_stack.drain(..) {
+                self.memory_used -= cs.memory_size();
+            }
+        }
+        self.current.changes.push(change);
+    }
+
+    // commit pending changes into undo stack if no group is open
+    pub fn commit(&mut self) {
+        if self.group_depth > 0 || self.current.is_empty() {
+            return;
+        }
+        let mut changeset = mem::replace(&mut self.current, Changeset::new());
+        changeset.changes.shrink_to_fit();
+        self.memory_used += changeset.memory_size();
+        self.undo_stack.push_back(changeset);
+        self.enforce_memory_limit();
+    }
+
+    // pop most recent changeset to undo. commits pending changes first.
+    // returns None if nothing to undo or a group is still open.
+    pub fn pop_undo(&mut self) -> Option<Changeset> {
+        if self.group_depth > 0 {
+            return None;
+        }
+        self.commit();
+        let changeset = self.undo_stack.pop_back()?;
+        self.memory_used -= changeset.memory_size();
+        Some(changeset)
+    }
+
+    pub fn pop_redo(&mut self) -> Option<Changeset> {
+        if self.group_depth > 0 {
+            return None;
+        }
+        let changeset = self.redo_stack.pop()?;
+        self.memory_used -= changeset.memory_size();
+        Some(changeset)
+    }
+
+    pub fn push_undo(&mut self, changeset: Changeset) {
+        self.memory_used += changeset.memory_size();
+        self.undo_stack.push_back(changeset);
+        self.enforce_memory_limit();
+    }
+
+    pub fn push_redo(&mut self, changeset: Changeset) {
+        self.memory_used += changeset.memory_size();
+        self.redo_stack.push(changeset);
+        self.enforce_memory_limit();
+    }
+
+    // drop oldest undo changesets, then oldest redo changesets,
+    // until memory used is within limit
+    fn enforce_memory_limit(&mut self) {
+        while self.memory_used > self.memory_limit {
+            if let Some(cs) = self.undo_stack.pop_front() {
+                self.memory_used -= cs.memory_size();
+            }
+            else if !self.redo_stack.is_empty() {
+                let cs = self.redo_stack.remove(0);
+                self.memory_used -= cs.memory_size();
+            }
+            else {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk_change(x: i32) -> Change {
+        Change::Chunk { p: Point::new(x, 0), prev: None, next: Some(1) }
+    }
+
+    #[test]
+    fn nested_groups_commit_once() {
+        let mut history = History::new();
+        history.begin_group();
+        history.record(chunk_change(0));
+        history.begin_group();
+        history.record(chunk_change(1));
+        history.end_group();
+        // inner group closed, outer still open
+        assert!(history.pop_undo().is_none());
+        history.end_group();
+
+        let changeset = history.pop_undo().unwrap();
+        assert_eq!(changeset.changes, vec![chunk_change(0), chunk_change(1)]);
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn undo_redo_stacks() {
+        let mut history = History::new();
+        for x in 0..3 {
+            history.record(chunk_change(x));
+            history.commit();
+        }
+
+        let last = history.pop_undo().unwrap();
+        assert_eq!(last.changes, vec![chunk_change(2)]);
+        history.push_redo(last);
+        assert!(history.can_redo());
+
+        let redo = history.pop_redo().unwrap();
+        assert_eq!(redo.changes, vec![chunk_change(2)]);
+        history.push_undo(redo);
+
+        // new change after undo clears redo
+        let undone = history.pop_undo().unwrap();
+        history.push_redo(undone);
+        history.record(chunk_change(3));
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn memory_limit_drops_oldest() {
+        let mut history = History::new();
+        for x in 0..10 {
+            history.record(chunk_change(x));
+            history.commit();
+        }
+        let used = history.memory_used();
+        assert!(used > 0);
+
+        history.set_memory_limit(used / 2);
+        assert!(history.memory_used() <= used / 2);
+
+        // newest changesets are kept
+        let newest = history.pop_undo().unwrap();
+        assert_eq!(newest.changes, vec![chunk_change(9)]);
+
+        let mut remaining = 0;
+        while history.pop_undo().is_some() {
+            remaining += 1;
+        }
+        assert!(remaining < 9);
+        assert_eq!(history.memory_used(), 0);
+    }
+
+    #[test]
+    fn memory_size_counts_territory_meta() {
+        let mut meta = Map::new();
+        meta.insert("name".to_string(), Value::from("x".repeat(1000)));
+        let empty = Changeset { changes: vec![Change::CreateTerritory { id: 0, color: None, meta: Box::new(Map::new()) }] };
+        let named = Changeset { changes: vec![Change::RemoveTerritory { id: 0, color: None, meta: Box::new(meta) }] };
+        assert!(named.memory_size() >= empty.memory_size() + 1000);
+    }
+}
@@ -0,0 +1,70 @@
+//! log.rs
+//! ----------------------------------------------------------------
+//! Logging hooks. Territory code logs with `log_debug!`, `log_info!`
+//! and `log_error!`, messages are passed to a hook function set by the
+//! host with `set_log_hook`: browser console in wasm builds (set on
+//! module start, see `wasm.rs`), stderr or any other sink in native
+//! builds. No hook is set by default, so logging is off. Messages are
+//! only formatted if a hook is set for their level.
+
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug = 0,
+    Info = 1,
+    Error = 2,
+}
+
+pub type LogHook = fn(LogLevel, &str);
+
+static HOOK: RwLock<Option<LogHook>> = RwLock::new(None);
+
+// min level passed to hook, u8 max = logging off
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(u8::MAX);
+
+/// Set hook receiving log messages at `level` and above,
+/// None turns logging off.
+pub fn set_log_hook(hook: Option<LogHook>, level: LogLevel) {
+    let mut current = HOOK.write().unwrap_or_else(|err| err.into_inner());
+    *current = hook;
+    let min_level = if hook.is_some() { level as u8 } else { u8::MAX };
+    MIN_LEVEL.store(min_level, Ordering::Relaxed);
+}
+
+pub fn log_enabled(level: LogLevel) -> bool {
+    level as u8 >= MIN_LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn log(level: LogLevel, msg: &str) {
+    if !log_enabled(level) {
+        return;
+    }
+    let hook = *HOOK.read().unwrap_or_else(|err| err.into_inner());
+    if let Some(hook) = hook {
+        hook(level, msg);
+    }
+}
+
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::territory::log::log_enabled($level) {
+            $crate::territory::log::log($level, &format!($($arg)*));
+        }
+    };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => { log_at!($crate::territory::log::LogLevel::Debug, $($arg)*) };
+}
+
+#[allow(unused_macros)]
+macro_rules! log_info {
+    ($($arg:tt)*) => { log_at!($crate::territory::log::LogLevel::Info, $($arg)*) };
+}
+
+#[allow(unused_macros)]
+macro_rules! log_error {
+    ($($arg:tt)*) => { log_at!($crate::territory::log::LogLevel::Error, $($arg)*) };
+}
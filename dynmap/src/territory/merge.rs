@@ -0,0 +1,252 @@
+//! merge.rs
+//! ----------------------------------------------------------------
+//! Three-way merge of ownership changes from two worlds `a` and `b`
+//! edited from the same `base` world.
+//!
+//! Merge works on the diffs base -> a and base -> b:
+//! - territories created in a keep their id, territories created in b
+//!   whose id was also created in a are remapped to a new id
+//! - chunk changed on one side only: change is applied
+//! - chunk changed on both sides to the same owner: change is applied
+//! - chunk changed on both sides to different owners: conflict, chunk
+//!   keeps its base owner
+//! - territories deleted on either side are deleted if they have no
+//!   chunks left after merging chunks, otherwise they are kept and
+//!   reported as territory conflicts (deleted on one side, but still
+//!   owning chunks changed or kept on the other side)
+
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+use fnv::{FnvHashMap, FnvHashSet};
+use territory::diff::WorldDiff;
+use territory::geometry::Point;
+use territory::world::{WorldError, encode_owner};
+
+/// Chunk changed to different owners in a and b, None = unclaimed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkConflict {
+    pub p: Point<i32>,
+    pub base: Option<u32>,
+    pub a: Option<u32>,
+    pub b: Option<u32>,
+}
+
+/// Result of `World::merge`
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MergeResult {
+    // (id in b, id in merged world), sorted by id in b
+    remapped: Vec<(u32, u32)>,
+    // sorted by chunk x, y
+    conflicts: Vec<ChunkConflict>,
+    // sorted ids
+    territory_conflicts: Vec<u32>,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl MergeResult {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=hasConflicts))]
+    pub fn has_conflicts(&self) -> bool {
+        !self.conflicts.is_empty() || !self.territory_conflicts.is_empty()
+    }
+
+    /// Remapped territory ids created in b, buffer
+    /// [id in b 1, merged id 1, id in b 2, merged id 2, ...]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getRemapped))]
+    pub fn get_remapped(&self) -> Vec<u32> {
+        self.remapped.iter().flat_map(|(id, new_id)| vec![*id, *new_id]).collect()
+    }
+
+    /// Conflicting chunks buffer [x1, y1, base1, a1, b1, x2, y2, ...]
+    /// with owners in merged world in `encode_owner` format (territory
+    /// id + 1, 0 = unclaimed), coords are i32 bit patterns (read with
+    /// `| 0` or an Int32Array)
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getConflicts))]
+    pub fn get_conflicts(&self) -> Vec<u32> {
+        self.conflicts.iter()
+            .flat_map(|c| vec![c.p.x as u32, c.p.y as u32, encode_owner(c.base), encode_owner(c.a), encode_owner(c.b)])
+            .collect()
+    }
+
+    /// Territories deleted on one side but kept because they still
+    /// own chunks after merge, sorted
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryConflicts))]
+    pub fn get_territory_conflicts(&self) -> Vec<u32> {
+        self.territory_conflicts.clone()
+    }
+}
+
+/// Changes to apply to base world to get merged world
+pub struct MergePlan {
+    // (id in source world, id in merged world, from b)
+    pub created: Vec<(u32, u32, bool)>,
+    // chunk owner changes in merged ids, sorted by chunk x, y
+    pub chunks: Vec<(Point<i32>, Option<u32>)>,
+    // territories to delete if empty after chunk changes, sorted
+    pub deleted: Vec<u32>,
+    pub result: MergeResult,
+}
+
+impl MergePlan {
+    /// Called when applying plan: territory deleted on a side was
+    /// kept because it still owns chunks.
+    pub fn keep_deleted(&mut self, id: u32) {
+        self.result.territory_conflicts.push(id);
+    }
+
+    pub fn into_result(self) -> MergeResult {
+        self.result
+    }
+}
+
+/// Merge diffs base -> a and base -> b. New ids for remapped
+/// territories are allocated starting from `next_id`, skipping
+/// `base_ids` (territories in base world) and created ids.
+/// Error if no id below u32 max is left for a remapped territory.
+pub fn merge_diffs(diff_a: &WorldDiff, diff_b: &WorldDiff, base_ids: &[u32], next_id: u32) -> Result<MergePlan, WorldError> {
+    let created_a: FnvHashSet<u32> = diff_a.created_ids().into_iter().collect();
+    let mut used_ids: FnvHashSet<u32> = created_a.clone();
+    used_ids.extend(diff_b.created_ids());
+    used_ids.extend(base_ids.iter().cloned());
+
+    let mut next_id = next_id;
+    let mut remap: FnvHashMap<u32, u32> = FnvHashMap::default();
+    let mut remapped: Vec<(u32, u32)> = Vec::new();
+    let mut created: Vec<(u32, u32, bool)> = diff_a.created_ids().into_iter().map(|id| (id, id, false)).collect();
+    for id in diff_b.created_ids().iter() {
+        let mut new_id = *id;
+        if created_a.contains(id) {
+            while used_ids.contains(&next_id) {
+                next_id = next_id.checked_add(1).ok_or(WorldError::TerritoryIdOutOfRange(next_id))?;
+            }
+            // id counter must stay above every id
+            if next_id == u32::MAX {
+                return Err(WorldError::TerritoryIdOutOfRange(next_id));
+            }
+            new_id = next_id;
+            used_ids.insert(new_id);
+            remap.insert(*id, new_id);
+            remapped.push((*id, new_id));
+        }
+        created.push((*id, new_id, true));
+    }
+    let remap_b = |id: Option<u32>| id.map(|id| remap.get(&id).cloned().unwrap_or(id));
+
+    let changes_b: FnvHashMap<Point<i32>, Option<u32>> = diff_b.chunks().iter()
+        .map(|c| (c.p, remap_b(c.next)))
+        .collect();
+
+    let mut chunks: Vec<(Point<i32>, Option<u32>)> = Vec::new();
+    let mut conflicts: Vec<ChunkConflict> = Vec::new();
+    for c in diff_a.chunks().iter() {
+        match changes_b.get(&c.p) {
+            Some(next_b) if *next_b != c.next => {
+                conflicts.push(ChunkConflict {
+                    p: c.p,
+                    base: c.prev,
+                    a: c.next,
+                    b: *next_b,
+                });
+            },
+            _ => chunks.push((c.p, c.next)),
+        }
+    }
+    let changed_a: FnvHashSet<Point<i32>> = diff_a.chunks().iter().map(|c| c.p).collect();
+    for (p, next_b) in changes_b.iter() {
+        if !changed_a.contains(p) {
+            chunks.push((*p, *next_b));
+        }
+    }
+    chunks.sort_by_key(|(p, _)| (p.x, p.y));
+    conflicts.sort_by_key(|c| (c.p.x, c.p.y));
+
+    let mut deleted: Vec<u32> = diff_a.deleted().iter().chain(diff_b.deleted().iter()).cloned().collect();
+    deleted.sort();
+    deleted.dedup();
+
+    Ok(MergePlan {
+        created,
+        chunks,
+        deleted,
+        result: MergeResult {
+            remapped,
+            conflicts,
+            territory_conflicts: Vec::new(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use territory::world::World;
+
+    fn diff(json: &str) -> WorldDiff {
+        WorldDiff::from_json_str(json).unwrap()
+    }
+
+    #[test]
+    fn remaps_colliding_ids_above_used_ids() {
+        let diff_a = diff(r#"{"created": [5], "chunks": [[0, 0, null, 5]]}"#);
+        let diff_b = diff(r#"{"created": [5, 6], "chunks": [[1, 0, null, 5], [2, 0, null, 6]]}"#);
+
+        // 5, 6 created, 7 in base
+        let plan = merge_diffs(&diff_a, &diff_b, &[1, 7], 5).unwrap();
+        assert_eq!(plan.created, vec![(5, 5, false), (5, 8, true), (6, 6, true)]);
+        assert_eq!(plan.chunks, vec![
+            (Point::new(0, 0), Some(5)),
+            (Point::new(1, 0), Some(8)),
+            (Point::new(2, 0), Some(6)),
+        ]);
+        assert_eq!(plan.result.get_remapped(), vec![5, 8]);
+        assert!(!plan.result.has_conflicts());
+    }
+
+    #[test]
+    fn rejects_remap_past_max_id() {
+        let diff_a = diff(r#"{"created": [5]}"#);
+        let diff_b = diff(r#"{"created": [5]}"#);
+
+        let plan = merge_diffs(&diff_a, &diff_b, &[u32::MAX - 1], u32::MAX - 2).unwrap();
+        assert_eq!(plan.result.get_remapped(), vec![5, u32::MAX - 2]);
+        assert_eq!(
+            merge_diffs(&diff_a, &diff_b, &[u32::MAX - 1], u32::MAX - 1).err(),
+            Some(WorldError::TerritoryIdOutOfRange(u32::MAX)),
+        );
+        assert!(merge_diffs(&diff_a, &diff_b, &[], u32::MAX).is_err());
+    }
+
+    #[test]
+    fn conflicting_chunk_changes() {
+        let diff_a = diff(r#"{"deleted": [3], "chunks": [[0, 0, 1, 2], [1, 0, 1, 2], [2, 0, 3, null]]}"#);
+        let diff_b = diff(r#"{"deleted": [3], "chunks": [[0, 0, 1, null], [1, 0, 1, 2], [2, 0, 3, null]]}"#);
+
+        let plan = merge_diffs(&diff_a, &diff_b, &[1, 2, 3], 4).unwrap();
+        // same change on both sides is not a conflict
+        assert_eq!(plan.chunks, vec![(Point::new(1, 0), Some(2)), (Point::new(2, 0), None)]);
+        assert_eq!(plan.result.get_conflicts(), vec![0, 0, 2, 3, 0]);
+        assert_eq!(plan.deleted, vec![3]);
+    }
+
+    #[test]
+    fn keeps_deleted_territory_with_chunks() {
+        let mut base = World::new(16).unwrap();
+        for id in 0..2 {
+            base.create_territory(Some(id)).unwrap();
+            base.add_coords_to_territory(id, vec![id as i32, 0]);
+        }
+        let json = base.to_world_json();
+
+        // a deletes territory 1, b claims a new chunk for it
+        let mut a = World::from_world_json(&json, 16).unwrap();
+        a.delete_territory(1);
+        let mut b = World::from_world_json(&json, 16).unwrap();
+        b.add_coords_to_territory(1, vec![2, 0]);
+
+        let result = base.merge(&a, &b).unwrap();
+        assert_eq!(result.get_territory_conflicts(), vec![1]);
+        assert_eq!(base.territory_at_chunk(1, 0), None);
+        assert_eq!(base.territory_at_chunk(2, 0), Some(1));
+    }
+}
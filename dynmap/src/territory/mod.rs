@@ -1,6 +1,23 @@
+// first, log macros are used by modules below
+#[macro_use]
+pub mod log;
+
+pub mod adjacency;
+pub mod diff;
+pub mod distance;
 pub mod generator;
 pub mod geometry;
+pub mod graph;
+pub mod history;
+pub mod merge;
 pub mod polygon;
+pub mod raster;
 pub mod sampler;
+pub mod snapshot;
+pub mod spatial;
+pub mod stats;
+#[allow(clippy::module_inception)]
 pub mod territory;
-pub mod world;
\ No newline at end of file
+pub mod validate;
+pub mod world;
+pub mod world_json;
\ No newline at end of file
@@ -1,3 +1,5 @@
+#![allow(clippy::empty_line_after_doc_comments, clippy::needless_return)]
+
 /// polygon.rs
 /// ----------------------------------------------------------------
 /// Polygon algorithms:
@@ -15,20 +17,6 @@ use thiserror::Error;
 
 use territory::geometry::{Point, AABB};
 
-use wasm_bindgen::prelude::*;
-
-#[wasm_bindgen]
-extern {
-    #[wasm_bindgen(js_namespace = console)]
-    fn log(msg: &str);
-
-    #[wasm_bindgen(js_namespace = console)]
-    fn error(msg: &str);
-}
-
-fn log1(s: &str) {
-    // log(s);
-}
 
 /// Possible Polylabel errors
 #[derive(Error, Debug, PartialEq)]
@@ -57,6 +45,7 @@ where T: Float
 }
 
 /// Calculate simple area of a polygon with assumptions:
+#[allow(clippy::ptr_arg)]
 fn get_area<T>(polygon: &Vec<Point<T>>) -> T
 where T: Float + FromPrimitive + Sum,
 {
@@ -77,6 +66,7 @@ where T: Float + FromPrimitive + Sum,
 /// Calculate simple centroid with assumptions:
 /// - polygon contains no holes
 /// - polygon is not a flat line
+#[allow(clippy::clone_on_copy)]
 fn get_centroid<T>(polygon: &Vec<Point<T>>) -> Point<T>
 where T: Float + FromPrimitive + Sum,
 {
@@ -103,6 +93,7 @@ where T: Float + FromPrimitive + Sum,
 }
 
 /// Calculate if `Point` p contained inside a polygon
+#[allow(clippy::ptr_arg)]
 fn point_position_from_polygon<T>(p: Point<T>, polygon: &Vec<Point<T>>) -> PointPosition
 where T: Float
 {
@@ -137,8 +128,9 @@ where T: Float
     }
 }
 
-// check if polygon contains a point
-fn polygon_contains<T>(polygon: &Vec<Point<T>>, p: Point<T>) -> bool 
+// check if polygon contains a point, polygon must be a closed loop
+#[allow(clippy::match_like_matches_macro)]
+pub fn polygon_contains<T>(polygon: &Vec<Point<T>>, p: Point<T>) -> bool 
 where T: Float
 {
     match point_position_from_polygon(p, polygon) {
@@ -149,7 +141,7 @@ where T: Float
 
 // distance from point to line [start, end]
 // https://en.wikipedia.org/wiki/Distance_from_a_point_to_a_line
-fn point_distance_to_line_segment<T>(p: Point<T>, start: Point<T>, end: Point<T>) -> T 
+pub fn point_distance_to_line_segment<T>(p: Point<T>, start: Point<T>, end: Point<T>) -> T 
 where T: Float
 {
     if start == end {
@@ -174,6 +166,7 @@ where T: Float
 }
 
 // shortest distance from point to a connected set of points
+#[allow(clippy::ptr_arg)]
 fn shortest_distance_to_path<T>(p: Point<T>, path: &Vec<Point<T>>) -> T 
 where T: Float
 {
@@ -312,6 +305,15 @@ fn add_quad<T>(
 /// The calculation uses an [iterative grid-based algorithm](https://github.com/mapbox/polylabel#how-the-algorithm-works).
 pub fn get_core<T>(polygon: &Vec<Point<T>>, tolerance: T) -> Result<Point<T>, PolygonError>
 where T: Float + Signed + Bounded + FromPrimitive + Sum + std::fmt::Debug + std::fmt::Display,
+{
+    get_core_with_distance(polygon, tolerance).map(|(core, _)| core)
+}
+
+/// Pole of inaccessibility and its distance to the polygon outline
+/// (radius of largest inscribed circle, within tolerance).
+#[allow(clippy::unnecessary_lazy_evaluations)]
+pub fn get_core_with_distance<T>(polygon: &Vec<Point<T>>, tolerance: T) -> Result<(Point<T>, T), PolygonError>
+where T: Float + Signed + Bounded + FromPrimitive + Sum + std::fmt::Debug + std::fmt::Display,
 {
 
     let two = T::from_i32(2).unwrap();
@@ -320,8 +322,8 @@ where T: Float + Signed + Bounded + FromPrimitive + Sum + std::fmt::Debug + std:
     let centroid = get_centroid(polygon);
     let bbox = AABB::from_polygon(polygon);
 
-    log1(&format!("centroid: x,y = {} {}", centroid.x, centroid.y));
-    log1(&format!("{:?}", bbox));
+    log_debug!("centroid: x,y = {} {}", centroid.x, centroid.y);
+    log_debug!("{:?}", bbox);
 
     // Ok(Point::new(centroid.x, centroid.y))
 
@@ -331,7 +333,7 @@ where T: Float + Signed + Bounded + FromPrimitive + Sum + std::fmt::Debug + std:
 
     // special case for degenerate polygons
     if cell_size == T::zero() {
-        return Ok(Point::new(bbox.min.x, bbox.min.y));
+        return Ok((Point::new(bbox.min.x, bbox.min.y), T::zero()));
     }
 
     let mut h = cell_size / two;
@@ -407,5 +409,5 @@ where T: Float + Signed + Bounded + FromPrimitive + Sum + std::fmt::Debug + std:
     }
 
     // exhausted the queue, return the best solution we've found
-    Ok(Point::new(best_cell.centroid.x, best_cell.centroid.y))
+    Ok((Point::new(best_cell.centroid.x, best_cell.centroid.y), best_cell.distance))
 }
@@ -0,0 +1,207 @@
+//! raster.rs
+//! ----------------------------------------------------------------
+//! Rasterize block-space shapes (polygon, rectangle, thick polyline)
+//! into chunk coords. A chunk is inside a shape if its center block
+//! coord is inside the shape, chunk c has center at
+//! c * grid_scale + grid_scale / 2.
+
+use fnv::FnvHashSet;
+use territory::geometry::{AABB, Point};
+use territory::polygon::{polygon_contains, point_distance_to_line_segment};
+use territory::world::WorldError;
+
+// block space center of chunk
+fn chunk_center(x: i32, y: i32, grid_scale: i32) -> Point<f64> {
+    let s = grid_scale as f64;
+    Point::new(x as f64 * s + 0.5 * s, y as f64 * s + 0.5 * s)
+}
+
+// max number of chunks scanned when rasterizing a shape
+pub const MAX_SHAPE_SCAN_SIZE: u64 = 1 << 24;
+
+// chunk coord range [xmin, xmax] covering block coords [min, max],
+// padded by one chunk. Error if coords are not finite or outside i32.
+fn chunk_range(min: f64, max: f64, grid_scale: i32) -> Result<(i32, i32), WorldError> {
+    if !min.is_finite() || !max.is_finite() {
+        return Err(WorldError::InvalidShape);
+    }
+    let s = grid_scale as f64;
+    let to_chunk = |v: f64| if v >= i32::MIN as f64 && v <= i32::MAX as f64 { Some(v as i32) } else { None };
+    let cmin = to_chunk((min / s).floor()).and_then(|c| c.checked_sub(1));
+    let cmax = to_chunk((max / s).ceil()).and_then(|c| c.checked_add(1));
+    match (cmin, cmax) {
+        (Some(cmin), Some(cmax)) => Ok((cmin, cmax)),
+        _ => Err(WorldError::InvalidShape),
+    }
+}
+
+// error if any point is not finite (NaN is skipped by aabb
+// min/max comparisons, so check explicitly)
+fn check_finite(points: &[Point<f64>]) -> Result<(), WorldError> {
+    if points.iter().all(|p| p.x.is_finite() && p.y.is_finite()) {
+        return Ok(());
+    }
+    Err(WorldError::InvalidShape)
+}
+
+// chunks whose centers may lie inside block space aabb, `scanned` is
+// total chunks scanned so far by this shape, limited to
+// MAX_SHAPE_SCAN_SIZE
+fn chunks_in_block_aabb<F>(aabb: &AABB<f64>, grid_scale: i32, scanned: &mut u64, mut inside: F) -> Result<Vec<Point<i32>>, WorldError>
+where F: FnMut(Point<f64>) -> bool
+{
+    let (xmin, xmax) = chunk_range(aabb.min.x, aabb.max.x, grid_scale)?;
+    let (ymin, ymax) = chunk_range(aabb.min.y, aabb.max.y, grid_scale)?;
+
+    let width = (xmax as i64 - xmin as i64 + 1) as u64;
+    let height = (ymax as i64 - ymin as i64 + 1) as u64;
+    *scanned = scanned.saturating_add(width.saturating_mul(height));
+    if *scanned > MAX_SHAPE_SCAN_SIZE {
+        return Err(WorldError::RegionTooLarge(*scanned, MAX_SHAPE_SCAN_SIZE));
+    }
+
+    let mut chunks: Vec<Point<i32>> = Vec::new();
+    for x in xmin..=xmax {
+        for y in ymin..=ymax {
+            if inside(chunk_center(x, y, grid_scale)) {
+                chunks.push(Point::new(x, y));
+            }
+        }
+    }
+    Ok(chunks)
+}
+
+/// Convert flat buffer [x1, y1, x2, y2, ...] into points,
+/// ignores trailing odd value.
+pub fn points_from_buffer(buffer: &[f64]) -> Vec<Point<f64>> {
+    buffer.chunks_exact(2).map(|p| Point::new(p[0], p[1])).collect()
+}
+
+/// Chunks with center inside polygon. Polygon does not need to be
+/// closed (last point = first point), it is closed internally.
+/// Error if coords are not finite or shape covers too many chunks.
+pub fn rasterize_polygon(polygon: &[Point<f64>], grid_scale: i32) -> Result<Vec<Point<i32>>, WorldError> {
+    if polygon.len() < 3 {
+        return Ok(Vec::new());
+    }
+    check_finite(polygon)?;
+
+    let mut polygon = polygon.to_vec();
+    if polygon[0] != polygon[polygon.len()-1] {
+        polygon.push(polygon[0]);
+    }
+
+    let aabb = AABB::from_polygon(&polygon);
+    chunks_in_block_aabb(&aabb, grid_scale, &mut 0, |p| polygon_contains(&polygon, p))
+}
+
+/// Chunks with center inside rectangle [xmin, xmax] x [ymin, ymax] (inclusive).
+pub fn rasterize_rect(xmin: f64, ymin: f64, xmax: f64, ymax: f64, grid_scale: i32) -> Result<Vec<Point<i32>>, WorldError> {
+    check_finite(&[Point::new(xmin, ymin), Point::new(xmax, ymax)])?;
+    if xmin > xmax || ymin > ymax {
+        return Ok(Vec::new());
+    }
+    let aabb = AABB::new(Point::new(xmin, ymin), Point::new(xmax, ymax));
+    chunks_in_block_aabb(&aabb, grid_scale, &mut 0, |p| aabb.contains(&p))
+}
+
+/// Chunks with center within width / 2 of polyline path.
+/// Single point path rasterizes a circle.
+pub fn rasterize_polyline(path: &[Point<f64>], width: f64, grid_scale: i32) -> Result<Vec<Point<i32>>, WorldError> {
+    if !width.is_finite() {
+        return Err(WorldError::InvalidShape);
+    }
+    check_finite(path)?;
+    if path.is_empty() || width <= 0.0 {
+        return Ok(Vec::new());
+    }
+
+    let radius = 0.5 * width;
+    let mut chunks: FnvHashSet<Point<i32>> = FnvHashSet::default();
+    let mut scanned: u64 = 0;
+
+    // rasterize each segment separately so long diagonal paths
+    // do not scan the whole path bounding box
+    for i in 0..path.len().max(2)-1 {
+        let start = path[i];
+        let end = if i + 1 < path.len() { path[i+1] } else { start };
+        let aabb = AABB::new(
+            Point::new(start.x.min(end.x) - radius, start.y.min(end.y) - radius),
+            Point::new(start.x.max(end.x) + radius, start.y.max(end.y) + radius),
+        );
+        chunks.extend(chunks_in_block_aabb(&aabb, grid_scale, &mut scanned, |p| point_distance_to_line_segment(p, start, end) <= radius)?);
+    }
+
+    Ok(chunks.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut chunks: Vec<Point<i32>>) -> Vec<(i32, i32)> {
+        chunks.sort_by_key(|p| (p.x, p.y));
+        chunks.into_iter().map(|p| (p.x, p.y)).collect()
+    }
+
+    #[test]
+    fn rect_covers_chunk_centers() {
+        // centers of chunks 0..=1 are at 8 and 24 blocks
+        let chunks = rasterize_rect(0.0, 0.0, 31.0, 15.0, 16).unwrap();
+        assert_eq!(sorted(chunks), vec![(0, 0), (1, 0)]);
+        assert!(rasterize_rect(10.0, 0.0, 0.0, 10.0, 16).unwrap().is_empty());
+    }
+
+    #[test]
+    fn polygon_and_polyline_rasterize() {
+        let square = points_from_buffer(&[0.0, 0.0, 32.0, 0.0, 32.0, 32.0, 0.0, 32.0]);
+        assert_eq!(sorted(rasterize_polygon(&square, 16).unwrap()), vec![(0, 0), (0, 1), (1, 0), (1, 1)]);
+
+        let path = points_from_buffer(&[8.0, 8.0, 40.0, 8.0]);
+        assert_eq!(sorted(rasterize_polyline(&path, 2.0, 16).unwrap()), vec![(0, 0), (1, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn rejects_non_finite_coords() {
+        assert!(matches!(rasterize_rect(f64::NAN, 0.0, 10.0, 10.0, 16), Err(WorldError::InvalidShape)));
+        assert!(matches!(rasterize_rect(0.0, 0.0, f64::INFINITY, 10.0, 16), Err(WorldError::InvalidShape)));
+        let triangle = points_from_buffer(&[0.0, 0.0, f64::NAN, 0.0, 0.0, 10.0]);
+        assert!(matches!(rasterize_polygon(&triangle, 16), Err(WorldError::InvalidShape)));
+        let path = points_from_buffer(&[0.0, 0.0, 10.0, 0.0]);
+        assert!(matches!(rasterize_polyline(&path, f64::NAN, 16), Err(WorldError::InvalidShape)));
+        assert!(matches!(rasterize_polyline(&path, f64::INFINITY, 16), Err(WorldError::InvalidShape)));
+    }
+
+    #[test]
+    fn rejects_coords_outside_chunk_range() {
+        // chunk bounds plus padding must fit in i32
+        let max = i32::MAX as f64;
+        assert!(matches!(rasterize_rect(0.0, 0.0, max, 0.0, 1), Err(WorldError::InvalidShape)));
+        assert!(matches!(rasterize_rect(-max - 1.0, 0.0, 0.0, 0.0, 1), Err(WorldError::InvalidShape)));
+        assert!(matches!(rasterize_rect(1e300, 0.0, 1e300, 0.0, 16), Err(WorldError::InvalidShape)));
+
+        // small shape near the limit is fine
+        let chunks = rasterize_rect(max - 3.0, 0.0, max - 2.0, 1.0, 1).unwrap();
+        assert_eq!(sorted(chunks), vec![(i32::MAX - 3, 0)]);
+    }
+
+    #[test]
+    fn rejects_huge_shapes() {
+        assert!(matches!(
+            rasterize_rect(0.0, 0.0, 1e9, 1e9, 16),
+            Err(WorldError::RegionTooLarge(_, MAX_SHAPE_SCAN_SIZE)),
+        ));
+
+        // limit applies to total scanned area of all polyline segments
+        let mut buffer = Vec::new();
+        for i in 0..200 {
+            let y = if i % 2 == 0 { 0.0 } else { 4000.0 };
+            buffer.extend_from_slice(&[0.0, y]);
+        }
+        let path = points_from_buffer(&buffer);
+        assert!(matches!(
+            rasterize_polyline(&path, 8000.0, 1),
+            Err(WorldError::RegionTooLarge(_, MAX_SHAPE_SCAN_SIZE)),
+        ));
+    }
+}
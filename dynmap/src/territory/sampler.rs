@@ -1,3 +1,5 @@
+#![allow(clippy::empty_line_after_doc_comments)]
+
 /// Handles random sampling from array of ints and probabilities
 /// 
 /// Interface has single methods of sampling
@@ -13,26 +15,26 @@
 /// https://www.keithschwarz.com/interesting/code/?dir=alias-method
 /// 
 
-extern crate wasm_bindgen;
 
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 use rand::prelude::*;
 use rand::rngs::SmallRng;
 use rand::distributions::weighted::WeightedIndex;
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Debug)]
 pub struct IndexSampler {
     rng: SmallRng,
     dist: WeightedIndex<f64>,
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl IndexSampler {
 
     // Create from input array buffer with format
     // [i1, p_i1, i2, p_i2, ...]
-    #[wasm_bindgen(js_name=fromWeights)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=fromWeights))]
     pub fn from_weights(random_seed: Option<u32>, weights: Vec<f64>) -> Self {
         let rng = if let Some(seed) = random_seed {
             rand::rngs::SmallRng::seed_from_u64(seed as u64)
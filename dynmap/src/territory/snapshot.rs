@@ -0,0 +1,341 @@
+//! snapshot.rs
+//! ----------------------------------------------------------------
+//! Compact versioned binary World snapshot, for autosaving to browser
+//! storage and fast loading of big maps.
+//!
+//! All integers are LEB128 varints, signed integers are zigzag encoded.
+//! Format (version 2):
+//! [
+//!     "MCTW",                 magic bytes
+//!     version,                u8
+//!     grid_scale,             signed
+//!     territory_id_counter,
+//!     len, json,              world json fields (meta, nodes, ...), utf8
+//!     N,                      num territories (strictly increasing ids)
+//!     id,                     territory 1
+//!     color,                  0 = none, else color + 1 (varint, so
+//!                             every u8 color is distinct from none)
+//!     len, json,              territory meta json (0 length = empty)
+//!     chunks,                 run length encoded chunks (below)
+//!     id,                     territory 2
+//!     ...
+//! ]
+//!
+//! Chunks are sorted into rows (by y), each row stores runs of
+//! consecutive x coords. Reader rejects runs past the declared number
+//! of chunks, so corrupt data cannot expand into huge chunk lists:
+//! [
+//!     C,                      num chunks
+//!     R,                      num rows
+//!     dy, M,                  row y - previous row y (signed), num runs
+//!     dx, n,                  run start x - previous run end x (signed,
+//!     ...                     row starts at 0), run length
+//! ]
+
+use std::convert::TryFrom;
+use fnv::FnvHashSet;
+use territory::geometry::Point;
+use territory::world::WorldError;
+
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"MCTW";
+pub const SNAPSHOT_VERSION: u8 = 2;
+
+fn invalid(msg: &str) -> WorldError {
+    WorldError::InvalidSnapshot(msg.to_string())
+}
+
+pub struct SnapshotWriter {
+    buffer: Vec<u8>,
+}
+
+impl Default for SnapshotWriter {
+    fn default() -> SnapshotWriter {
+        SnapshotWriter::new()
+    }
+}
+
+impl SnapshotWriter {
+    pub fn new() -> SnapshotWriter {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(SNAPSHOT_MAGIC);
+        buffer.push(SNAPSHOT_VERSION);
+        SnapshotWriter {
+            buffer,
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    pub fn write_u8(&mut self, val: u8) {
+        self.buffer.push(val);
+    }
+
+    pub fn write_varint(&mut self, mut val: u64) {
+        loop {
+            let byte = (val & 0x7f) as u8;
+            val >>= 7;
+            if val == 0 {
+                self.buffer.push(byte);
+                return;
+            }
+            self.buffer.push(byte | 0x80);
+        }
+    }
+
+    pub fn write_zigzag(&mut self, val: i64) {
+        self.write_varint(((val << 1) ^ (val >> 63)) as u64);
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_varint(s.len() as u64);
+        self.buffer.extend_from_slice(s.as_bytes());
+    }
+
+    pub fn write_chunks(&mut self, coords: &FnvHashSet<Point<i32>>) {
+        let mut sorted: Vec<(i32, i32)> = coords.iter().map(|p| (p.y, p.x)).collect();
+        sorted.sort();
+
+        // rows of runs (y, [(x start, length), ...])
+        let mut rows: Vec<(i32, Vec<(i32, i32)>)> = Vec::new();
+        for (y, x) in sorted.into_iter() {
+            let new_row = rows.last().map_or(true, |row| row.0 != y);
+            if new_row {
+                rows.push((y, vec![(x, 1)]));
+                continue;
+            }
+            let runs = &mut rows.last_mut().unwrap().1;
+            let extends_run = {
+                let run = runs.last().unwrap();
+                run.0 + run.1 == x
+            };
+            if extends_run {
+                runs.last_mut().unwrap().1 += 1;
+            } else {
+                runs.push((x, 1));
+            }
+        }
+
+        self.write_varint(coords.len() as u64);
+        self.write_varint(rows.len() as u64);
+        let mut prev_y: i64 = 0;
+        for (y, runs) in rows.into_iter() {
+            self.write_zigzag(y as i64 - prev_y);
+            self.write_varint(runs.len() as u64);
+            prev_y = y as i64;
+
+            let mut prev_x: i64 = 0;
+            for (x, length) in runs.into_iter() {
+                self.write_zigzag(x as i64 - prev_x);
+                self.write_varint(length as u64);
+                prev_x = x as i64 + length as i64;
+            }
+        }
+    }
+}
+
+pub struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    /// Create reader, checks magic bytes and version
+    pub fn new(bytes: &'a [u8]) -> Result<SnapshotReader<'a>, WorldError> {
+        if bytes.len() < 5 || &bytes[0..4] != SNAPSHOT_MAGIC {
+            return Err(invalid("not a world snapshot"));
+        }
+        if bytes[4] != SNAPSHOT_VERSION {
+            return Err(WorldError::InvalidSnapshot(format!("unsupported version {}", bytes[4])));
+        }
+        Ok(SnapshotReader {
+            bytes,
+            pos: 5,
+        })
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, WorldError> {
+        let val = *self.bytes.get(self.pos).ok_or_else(|| invalid("unexpected end of data"))?;
+        self.pos += 1;
+        Ok(val)
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64, WorldError> {
+        let mut val: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if shift >= 64 {
+                return Err(invalid("varint overflow"));
+            }
+            val |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(val);
+            }
+            shift += 7;
+        }
+    }
+
+    pub fn read_zigzag(&mut self) -> Result<i64, WorldError> {
+        let val = self.read_varint()?;
+        Ok(((val >> 1) as i64) ^ -((val & 1) as i64))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, WorldError> {
+        let val = self.read_varint()?;
+        u32::try_from(val).map_err(|_| invalid("integer out of range"))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, WorldError> {
+        let val = self.read_zigzag()?;
+        i32::try_from(val).map_err(|_| invalid("integer out of range"))
+    }
+
+    pub fn read_str(&mut self) -> Result<&'a str, WorldError> {
+        let len = self.read_varint()? as usize;
+        let end = self.pos.checked_add(len).ok_or_else(|| invalid("unexpected end of data"))?;
+        if end > self.bytes.len() {
+            return Err(invalid("unexpected end of data"));
+        }
+        let s = std::str::from_utf8(&self.bytes[self.pos..end]).map_err(|_| invalid("invalid utf8 string"))?;
+        self.pos = end;
+        Ok(s)
+    }
+
+    pub fn read_chunks(&mut self) -> Result<Vec<Point<i32>>, WorldError> {
+        let num_chunks = self.read_u32()? as u64;
+        let mut chunks: Vec<Point<i32>> = Vec::new();
+        let num_rows = self.read_varint()?;
+        let mut y: i64 = 0;
+        for _ in 0..num_rows {
+            y = y.checked_add(self.read_zigzag()?).ok_or_else(|| invalid("chunk coord out of range"))?;
+            let row_y = i32::try_from(y).map_err(|_| invalid("chunk coord out of range"))?;
+            let num_runs = self.read_varint()?;
+            let mut x: i64 = 0;
+            for _ in 0..num_runs {
+                x = x.checked_add(self.read_zigzag()?).ok_or_else(|| invalid("chunk coord out of range"))?;
+                let length = self.read_varint()?;
+                if length > num_chunks - chunks.len() as u64 {
+                    return Err(invalid("chunk run exceeds chunk count"));
+                }
+                if x < i32::MIN as i64 || x > i32::MAX as i64 {
+                    return Err(invalid("chunk coord out of range"));
+                }
+                // x in i32 range and length <= u32 max, cannot overflow
+                let end = x + length as i64;
+                if end - 1 > i32::MAX as i64 {
+                    return Err(invalid("chunk coord out of range"));
+                }
+                for cx in x..end {
+                    chunks.push(Point::new(cx as i32, row_y));
+                }
+                x = end;
+            }
+        }
+        if chunks.len() as u64 != num_chunks {
+            return Err(invalid("chunk count mismatch"));
+        }
+        Ok(chunks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_round_trip() {
+        let unsigned = [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX];
+        let signed = [0, 1, -1, 63, -64, i32::MIN as i64, i32::MAX as i64, i64::MIN, i64::MAX];
+
+        let mut writer = SnapshotWriter::new();
+        writer.write_u8(7);
+        for v in unsigned.iter() {
+            writer.write_varint(*v);
+        }
+        for v in signed.iter() {
+            writer.write_zigzag(*v);
+        }
+        writer.write_str("territory ✓");
+        let bytes = writer.into_bytes();
+
+        let mut r = SnapshotReader::new(&bytes).unwrap();
+        assert_eq!(r.read_u8().unwrap(), 7);
+        for v in unsigned.iter() {
+            assert_eq!(r.read_varint().unwrap(), *v);
+        }
+        for v in signed.iter() {
+            assert_eq!(r.read_zigzag().unwrap(), *v);
+        }
+        assert_eq!(r.read_str().unwrap(), "territory ✓");
+        assert!(r.is_at_end());
+        assert!(r.read_u8().is_err());
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let mut coords: FnvHashSet<Point<i32>> = FnvHashSet::default();
+        for x in -5..5 {
+            coords.insert(Point::new(x, 0));
+            coords.insert(Point::new(x * 3, -7));
+        }
+        coords.insert(Point::new(i32::MIN, i32::MIN));
+        coords.insert(Point::new(i32::MAX, i32::MAX));
+        coords.insert(Point::new(i32::MAX - 1, i32::MAX));
+
+        let mut writer = SnapshotWriter::new();
+        writer.write_chunks(&coords);
+        writer.write_chunks(&FnvHashSet::default());
+        let bytes = writer.into_bytes();
+
+        let mut r = SnapshotReader::new(&bytes).unwrap();
+        let chunks: FnvHashSet<Point<i32>> = r.read_chunks().unwrap().into_iter().collect();
+        assert_eq!(chunks, coords);
+        assert!(r.read_chunks().unwrap().is_empty());
+        assert!(r.is_at_end());
+    }
+
+    #[test]
+    fn rejects_invalid_header() {
+        assert!(SnapshotReader::new(b"").is_err());
+        assert!(SnapshotReader::new(b"MCTX\x02").is_err());
+        assert!(SnapshotReader::new(&[b'M', b'C', b'T', b'W', SNAPSHOT_VERSION + 1]).is_err());
+    }
+
+    // chunk section with 1 row of 1 run
+    fn chunk_run(num_chunks: u64, y: i64, x: i64, length: u64) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new();
+        writer.write_varint(num_chunks);
+        writer.write_varint(1);
+        writer.write_zigzag(y);
+        writer.write_varint(1);
+        writer.write_zigzag(x);
+        writer.write_varint(length);
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn rejects_corrupt_chunks() {
+        let read = |bytes: Vec<u8>| SnapshotReader::new(&bytes).unwrap().read_chunks();
+
+        assert_eq!(read(chunk_run(3, 0, 0, 3)).unwrap().len(), 3);
+        // run longer than declared count, huge run
+        assert!(read(chunk_run(3, 0, 0, 4)).is_err());
+        assert!(read(chunk_run(3, 0, 0, u32::MAX as u64)).is_err());
+        // fewer chunks than declared
+        assert!(read(chunk_run(3, 0, 0, 2)).is_err());
+        // coords outside i32
+        assert!(read(chunk_run(1, i32::MAX as i64 + 1, 0, 1)).is_err());
+        assert!(read(chunk_run(2, 0, i32::MAX as i64, 2)).is_err());
+        assert!(read(chunk_run(1, 0, i64::MAX, 1)).is_err());
+        // truncated
+        let mut bytes = chunk_run(3, 0, 0, 3);
+        bytes.pop();
+        assert!(read(bytes).is_err());
+    }
+}
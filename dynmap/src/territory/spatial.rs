@@ -0,0 +1,425 @@
+//! spatial.rs
+//! ----------------------------------------------------------------
+//! Spatial index for world chunk and territory queries.
+//!
+//! Chunks are bucketed into square tiles of TILE_SIZE x TILE_SIZE
+//! chunks, each tile stores the number of chunks owned by each
+//! territory inside it. Region queries only visit tiles overlapping
+//! the region: tiles fully inside the region are answered from the
+//! tile counts, tiles on the region boundary are scanned chunk by
+//! chunk using the world grid.
+//!
+//! Per-territory AABBs are kept exact by counting chunks in each
+//! x column and y row of a territory (ordered maps, so min/max are
+//! the first/last keys). Occupied tiles are tracked the same way, so
+//! queries are clamped to occupied tiles, and queries covering more
+//! tiles than exist only visit existing tiles.
+//!
+//! Index must be updated on every chunk ownership change.
+
+use std::collections::BTreeMap;
+use fnv::{FnvHashMap, FnvHashSet};
+use territory::geometry::{AABB, Point};
+
+// tile size as power of 2 shift, tiles are 16 x 16 chunks
+const TILE_SHIFT: i32 = 4;
+const TILE_SIZE: i32 = 1 << TILE_SHIFT;
+
+// tile containing chunk, arithmetic shift floors negative coords
+fn tile_of(x: i32, y: i32) -> Point<i32> {
+    Point::new(x >> TILE_SHIFT, y >> TILE_SHIFT)
+}
+
+// chunk coord range covered by a tile, adds TILE_SIZE - 1 as one
+// term so the last tile before i32 max does not overflow
+fn tile_aabb(tile: &Point<i32>) -> AABB<i32> {
+    let xmin = tile.x << TILE_SHIFT;
+    let ymin = tile.y << TILE_SHIFT;
+    AABB::new(
+        Point::new(xmin, ymin),
+        Point::new(xmin + (TILE_SIZE - 1), ymin + (TILE_SIZE - 1)),
+    )
+}
+
+// intersection of two aabbs, None if they do not overlap
+fn aabb_intersection(a: &AABB<i32>, b: &AABB<i32>) -> Option<AABB<i32>> {
+    let xmin = a.min.x.max(b.min.x);
+    let ymin = a.min.y.max(b.min.y);
+    let xmax = a.max.x.min(b.max.x);
+    let ymax = a.max.y.min(b.max.y);
+    if xmin > xmax || ymin > ymax {
+        return None;
+    }
+    Some(AABB::new(Point::new(xmin, ymin), Point::new(xmax, ymax)))
+}
+
+fn aabb_contains_aabb(outer: &AABB<i32>, inner: &AABB<i32>) -> bool {
+    outer.contains(&inner.min) && outer.contains(&inner.max)
+}
+
+// chunk counts per column and row of a territory
+struct TerritoryBounds {
+    columns: BTreeMap<i32, u32>,
+    rows: BTreeMap<i32, u32>,
+}
+
+impl TerritoryBounds {
+    fn new() -> TerritoryBounds {
+        TerritoryBounds {
+            columns: BTreeMap::new(),
+            rows: BTreeMap::new(),
+        }
+    }
+
+    fn insert(&mut self, p: Point<i32>) {
+        *self.columns.entry(p.x).or_insert(0) += 1;
+        *self.rows.entry(p.y).or_insert(0) += 1;
+    }
+
+    fn remove(&mut self, p: Point<i32>) {
+        fn decrement(counts: &mut BTreeMap<i32, u32>, key: i32) {
+            if let Some(count) = counts.get_mut(&key) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(&key);
+                }
+            }
+        }
+        decrement(&mut self.columns, p.x);
+        decrement(&mut self.rows, p.y);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    fn aabb(&self) -> Option<AABB<i32>> {
+        let xmin = *self.columns.keys().next()?;
+        let xmax = *self.columns.keys().next_back()?;
+        let ymin = *self.rows.keys().next()?;
+        let ymax = *self.rows.keys().next_back()?;
+        Some(AABB::new(Point::new(xmin, ymin), Point::new(xmax, ymax)))
+    }
+}
+
+pub struct SpatialIndex {
+    // tile -> (territory id -> number of chunks in tile)
+    tiles: FnvHashMap<Point<i32>, FnvHashMap<u32, u32>>,
+    // bounds of occupied tiles, in tile coords
+    tile_bounds: TerritoryBounds,
+    // territory id -> exact chunk bounds
+    bounds: FnvHashMap<u32, TerritoryBounds>,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> SpatialIndex {
+        SpatialIndex::new()
+    }
+}
+
+impl SpatialIndex {
+    pub fn new() -> SpatialIndex {
+        SpatialIndex {
+            tiles: FnvHashMap::default(),
+            tile_bounds: TerritoryBounds::new(),
+            bounds: FnvHashMap::default(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+        self.tile_bounds = TerritoryBounds::new();
+        self.bounds.clear();
+    }
+
+    // mark chunk p as owned by territory id
+    pub fn insert(&mut self, p: Point<i32>, id: u32) {
+        let tile_key = tile_of(p.x, p.y);
+        if !self.tiles.contains_key(&tile_key) {
+            self.tile_bounds.insert(tile_key);
+        }
+        let tile = self.tiles.entry(tile_key).or_default();
+        *tile.entry(id).or_insert(0) += 1;
+        self.bounds.entry(id).or_insert_with(TerritoryBounds::new).insert(p);
+    }
+
+    // remove chunk p owned by territory id
+    pub fn remove(&mut self, p: Point<i32>, id: u32) {
+        let tile_key = tile_of(p.x, p.y);
+        let mut tile_is_empty = false;
+        if let Some(tile) = self.tiles.get_mut(&tile_key) {
+            if let Some(count) = tile.get_mut(&id) {
+                *count -= 1;
+                if *count == 0 {
+                    tile.remove(&id);
+                }
+            }
+            tile_is_empty = tile.is_empty();
+        }
+        if tile_is_empty {
+            self.tiles.remove(&tile_key);
+            self.tile_bounds.remove(tile_key);
+        }
+
+        let mut bounds_is_empty = false;
+        if let Some(bounds) = self.bounds.get_mut(&id) {
+            bounds.remove(p);
+            bounds_is_empty = bounds.is_empty();
+        }
+        if bounds_is_empty {
+            self.bounds.remove(&id);
+        }
+    }
+
+    // exact chunk bounding box of a territory, None if territory has no chunks
+    pub fn territory_aabb(&self, id: u32) -> Option<AABB<i32>> {
+        self.bounds.get(&id)?.aabb()
+    }
+
+    // occupied tiles inside tile range (inclusive). range is clamped to
+    // occupied tile bounds, if it still covers more tiles than exist,
+    // existing tiles are filtered instead of looking up each tile in range
+    fn tiles_in_range(&self, range: &AABB<i32>) -> Vec<(Point<i32>, &FnvHashMap<u32, u32>)> {
+        let range = match self.tile_bounds.aabb().and_then(|occupied| aabb_intersection(range, &occupied)) {
+            Some(range) => range,
+            None => return Vec::new(),
+        };
+
+        let num_tiles = (range.max.x as i64 - range.min.x as i64 + 1) * (range.max.y as i64 - range.min.y as i64 + 1);
+        if num_tiles > self.tiles.len() as i64 {
+            return self.tiles.iter()
+                .filter(|(tile_key, _)| range.contains(tile_key))
+                .map(|(tile_key, tile)| (*tile_key, tile))
+                .collect();
+        }
+
+        let mut tiles = Vec::new();
+        for tx in range.min.x..=range.max.x {
+            for ty in range.min.y..=range.max.y {
+                let tile_key = Point::new(tx, ty);
+                if let Some(tile) = self.tiles.get(&tile_key) {
+                    tiles.push((tile_key, tile));
+                }
+            }
+        }
+        tiles
+    }
+
+    // return ids of territories with chunks inside aabb (inclusive),
+    // `grid` must be the world chunk -> territory id grid this index tracks
+    pub fn query_aabb(&self, aabb: &AABB<i32>, grid: &FnvHashMap<Point<i32>, u32>) -> Vec<u32> {
+        let mut found: FnvHashSet<u32> = FnvHashSet::default();
+
+        let tile_range = AABB::new(
+            tile_of(aabb.min.x, aabb.min.y),
+            tile_of(aabb.max.x, aabb.max.y),
+        );
+
+        for (tile_key, tile) in self.tiles_in_range(&tile_range) {
+            // skip tile if all its territories already found
+            if tile.keys().all(|id| found.contains(id)) {
+                continue;
+            }
+
+            let tile_bounds = tile_aabb(&tile_key);
+            if aabb_contains_aabb(aabb, &tile_bounds) {
+                found.extend(tile.keys().cloned());
+                continue;
+            }
+
+            // partial overlap: territories whose whole aabb is inside
+            // query are found, otherwise scan overlapping chunks
+            let mut needs_scan = false;
+            for id in tile.keys() {
+                if found.contains(id) {
+                    continue;
+                }
+                match self.territory_aabb(*id) {
+                    Some(ref terr_aabb) if aabb_contains_aabb(aabb, terr_aabb) => {
+                        found.insert(*id);
+                    },
+                    _ => needs_scan = true,
+                }
+            }
+
+            if needs_scan {
+                if let Some(overlap) = aabb_intersection(aabb, &tile_bounds) {
+                    for x in overlap.min.x..=overlap.max.x {
+                        for y in overlap.min.y..=overlap.max.y {
+                            if let Some(id) = grid.get(&Point::new(x, y)) {
+                                found.insert(*id);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut ids: Vec<u32> = found.into_iter().collect();
+        ids.sort();
+        ids
+    }
+
+    // return ids of territories with chunks at distance < radius from
+    // chunk (cx, cy), same chunk selection as circle brushes
+    pub fn query_radius(&self, cx: i32, cy: i32, radius: f64, grid: &FnvHashMap<Point<i32>, u32>) -> Vec<u32> {
+        let mut found: FnvHashSet<u32> = FnvHashSet::default();
+        if radius <= 0.0 {
+            return Vec::new();
+        }
+
+        // float to int cast saturates, saturating ops keep circle
+        // aabb inside i32 range for huge radius or coords near limits
+        let r = radius.ceil() as i32;
+        let circle_aabb = AABB::new(
+            Point::new(cx.saturating_sub(r), cy.saturating_sub(r)),
+            Point::new(cx.saturating_add(r), cy.saturating_add(r)),
+        );
+        let tile_range = AABB::new(
+            tile_of(circle_aabb.min.x, circle_aabb.min.y),
+            tile_of(circle_aabb.max.x, circle_aabb.max.y),
+        );
+
+        // distance in f64, i32 differences can overflow
+        let in_circle = |x: i32, y: i32| (x as f64 - cx as f64).hypot(y as f64 - cy as f64) < radius;
+        let farthest = |min: i32, max: i32, c: i32| {
+            if (min as i64 - c as i64).abs() > (max as i64 - c as i64).abs() { min } else { max }
+        };
+
+        for (tile_key, tile) in self.tiles_in_range(&tile_range) {
+            if tile.keys().all(|id| found.contains(id)) {
+                continue;
+            }
+
+            // tile fully inside circle if its farthest corner chunk is inside
+            let b = tile_aabb(&tile_key);
+            let fx = farthest(b.min.x, b.max.x, cx);
+            let fy = farthest(b.min.y, b.max.y, cy);
+            if in_circle(fx, fy) {
+                found.extend(tile.keys().cloned());
+                continue;
+            }
+
+            // partial overlap, scan chunks in circle
+            if let Some(overlap) = aabb_intersection(&circle_aabb, &b) {
+                for x in overlap.min.x..=overlap.max.x {
+                    for y in overlap.min.y..=overlap.max.y {
+                        if !in_circle(x, y) {
+                            continue;
+                        }
+                        if let Some(id) = grid.get(&Point::new(x, y)) {
+                            found.insert(*id);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut ids: Vec<u32> = found.into_iter().collect();
+        ids.sort();
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+
+    fn brute_aabb(grid: &FnvHashMap<Point<i32>, u32>, aabb: &AABB<i32>) -> Vec<u32> {
+        let mut ids: Vec<u32> = grid.iter()
+            .filter(|(p, _)| aabb.contains(p))
+            .map(|(_, id)| *id)
+            .collect::<FnvHashSet<u32>>()
+            .into_iter()
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    fn brute_radius(grid: &FnvHashMap<Point<i32>, u32>, cx: i32, cy: i32, radius: f64) -> Vec<u32> {
+        let mut ids: Vec<u32> = grid.iter()
+            .filter(|(p, _)| (p.x as f64 - cx as f64).hypot(p.y as f64 - cy as f64) < radius)
+            .map(|(_, id)| *id)
+            .collect::<FnvHashSet<u32>>()
+            .into_iter()
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    fn set_owner(index: &mut SpatialIndex, grid: &mut FnvHashMap<Point<i32>, u32>, p: Point<i32>, owner: Option<u32>) {
+        if let Some(prev) = grid.remove(&p) {
+            index.remove(p, prev);
+        }
+        if let Some(id) = owner {
+            grid.insert(p, id);
+            index.insert(p, id);
+        }
+    }
+
+    fn check_queries(index: &SpatialIndex, grid: &FnvHashMap<Point<i32>, u32>, rng: &mut SmallRng) {
+        for _ in 0..200 {
+            let (x0, y0) = (rng.gen_range(-80..80), rng.gen_range(-80..80));
+            let aabb = AABB::new(Point::new(x0, y0), Point::new(x0 + rng.gen_range(0..60), y0 + rng.gen_range(0..60)));
+            assert_eq!(brute_aabb(grid, &aabb), index.query_aabb(&aabb, grid), "aabb {:?}", aabb);
+
+            let radius = rng.gen_range(0.0..40.0);
+            assert_eq!(brute_radius(grid, x0, y0, radius), index.query_radius(x0, y0, radius, grid), "radius {} at {} {}", radius, x0, y0);
+        }
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut index = SpatialIndex::new();
+        let mut grid: FnvHashMap<Point<i32>, u32> = FnvHashMap::default();
+
+        // blobs of random territories
+        for id in 0..30 {
+            let (cx, cy) = (rng.gen_range(-60..60), rng.gen_range(-60..60));
+            for _ in 0..rng.gen_range(1..80) {
+                let p = Point::new(cx + rng.gen_range(-6..7), cy + rng.gen_range(-6..7));
+                set_owner(&mut index, &mut grid, p, Some(id));
+            }
+        }
+        check_queries(&index, &grid, &mut rng);
+
+        // remove and move chunks, bounds must shrink with them
+        let points: Vec<Point<i32>> = grid.keys().cloned().collect();
+        for p in points.iter() {
+            match rng.gen_range(0..3) {
+                0 => set_owner(&mut index, &mut grid, *p, None),
+                1 => set_owner(&mut index, &mut grid, *p, Some(rng.gen_range(0..30))),
+                _ => (),
+            }
+        }
+        check_queries(&index, &grid, &mut rng);
+
+        for id in 0..30 {
+            let expected = AABB::from_points(grid.iter().filter(|(_, owner)| **owner == id).map(|(p, _)| *p));
+            match index.territory_aabb(id) {
+                Some(aabb) => assert_eq!(aabb, expected),
+                None => assert!(grid.values().all(|owner| *owner != id)),
+            }
+        }
+    }
+
+    #[test]
+    fn queries_near_coord_limits() {
+        let mut index = SpatialIndex::new();
+        let mut grid: FnvHashMap<Point<i32>, u32> = FnvHashMap::default();
+        set_owner(&mut index, &mut grid, Point::new(i32::MAX, i32::MAX), Some(1));
+        set_owner(&mut index, &mut grid, Point::new(i32::MIN, i32::MIN), Some(2));
+        set_owner(&mut index, &mut grid, Point::new(0, 0), Some(3));
+
+        let everything = AABB::new(Point::new(i32::MIN, i32::MIN), Point::new(i32::MAX, i32::MAX));
+        assert_eq!(index.query_aabb(&everything, &grid), vec![1, 2, 3]);
+        assert_eq!(index.query_radius(i32::MAX, i32::MAX, 2.0, &grid), vec![1]);
+        assert_eq!(index.query_radius(0, 0, 1e9, &grid), vec![3]);
+        assert_eq!(index.query_radius(0, 0, 1e12, &grid), vec![1, 2, 3]);
+        assert_eq!(index.query_radius(0, 0, f64::INFINITY, &grid), vec![1, 2, 3]);
+        assert!(index.query_radius(0, 0, 0.0, &grid).is_empty());
+    }
+}
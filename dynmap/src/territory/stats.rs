@@ -0,0 +1,222 @@
+//! stats.rs
+//! ----------------------------------------------------------------
+//! Territory shape statistics, for finding badly shaped territories:
+//!
+//! - chunk_area: number of chunks
+//! - block_area: chunk area * grid_scale^2
+//! - perimeter: border length in chunk edges
+//! - components: number of 4-connected components
+//! - holes: number of regions enclosed by the territory that are not
+//!   part of it (unclaimed or owned by other territories)
+//! - xmin, ymin, xmax, ymax: chunk bounding box
+//! - centroid_x, centroid_y: mean chunk coord
+//! - compactness: Polsby-Popper 4 pi A / P^2 in chunks
+//!   (1 = circle, lower = less compact)
+//! - inaccessibility_radius: distance in blocks from pole of
+//!   inaccessibility (`polygon::get_core`) of the territory border
+//!   to the border, uses largest border loop like `get_border` (so
+//!   holes inside the territory are ignored), NaN (null in json) if
+//!   border loops cannot be traced
+
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+use fnv::FnvHashMap;
+use serde_json::{Map, Value};
+use territory::geometry::Point;
+use territory::polygon::get_core_with_distance;
+use territory::territory::{Territory, NEIGHBORS_4};
+use territory::validate::find_holes;
+
+/// Result of `World::territory_stats`
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TerritoryStats {
+    pub id: u32,
+    pub chunk_area: u32,
+    pub block_area: f64,
+    pub perimeter: u32,
+    pub components: u32,
+    pub holes: u32,
+    pub xmin: i32,
+    pub ymin: i32,
+    pub xmax: i32,
+    pub ymax: i32,
+    pub centroid_x: f64,
+    pub centroid_y: f64,
+    pub compactness: f64,
+    pub inaccessibility_radius: f64,
+}
+
+impl TerritoryStats {
+    pub fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("id".to_string(), Value::from(self.id));
+        obj.insert("chunkArea".to_string(), Value::from(self.chunk_area));
+        obj.insert("blockArea".to_string(), Value::from(self.block_area));
+        obj.insert("perimeter".to_string(), Value::from(self.perimeter));
+        obj.insert("components".to_string(), Value::from(self.components));
+        obj.insert("holes".to_string(), Value::from(self.holes));
+        obj.insert("aabb".to_string(), Value::from(vec![self.xmin, self.ymin, self.xmax, self.ymax]));
+        obj.insert("centroid".to_string(), Value::from(vec![self.centroid_x, self.centroid_y]));
+        obj.insert("compactness".to_string(), Value::from(self.compactness));
+        obj.insert("inaccessibilityRadius".to_string(), Value::from(self.inaccessibility_radius));
+        Value::Object(obj)
+    }
+}
+
+// radius of largest inscribed circle of largest border loop, in blocks.
+// parses `Territory::try_get_border` output buffer, None if border fails.
+fn inaccessibility_radius(terr: &Territory, grid_scale: i32) -> Option<f64> {
+    let border = terr.try_get_border(grid_scale)?;
+    let num_loops = border[2] as usize;
+
+    let mut largest_loop: &[i32] = &[];
+    let mut i = 3;
+    for _ in 0..num_loops {
+        let num_points = border[i] as usize;
+        let num_edges = border[i + 1] as usize;
+        i += 2 + 2 * num_points;
+        if num_edges > largest_loop.len() / 2 {
+            largest_loop = &border[i..i + 2 * num_edges];
+        }
+        i += 2 * num_edges;
+    }
+
+    if largest_loop.len() < 6 {
+        return Some(0.0);
+    }
+
+    let polygon: Vec<Point<f64>> = largest_loop.chunks(2)
+        .map(|p| Point::new(p[0] as f64, p[1] as f64))
+        .collect();
+    Some(get_core_with_distance(&polygon, 1.0).map_or(0.0, |(_, distance)| distance.max(0.0)))
+}
+
+// chunk edges between territory chunks and chunks outside territory
+fn perimeter(terr: &Territory) -> u32 {
+    let mut perimeter: u32 = 0;
+    for p in terr.coords.iter() {
+        for (dx, dy) in NEIGHBORS_4.iter() {
+            if !terr.coords.contains(&Point::new(p.x + dx, p.y + dy)) {
+                perimeter += 1;
+            }
+        }
+    }
+    perimeter
+}
+
+/// Calculate shape statistics of territory. Does not panic on shapes
+/// whose border cannot be traced, see `inaccessibility_radius`.
+pub fn territory_stats(terr: &Territory, grid_scale: i32) -> TerritoryStats {
+    let chunk_area = terr.coords.len() as u32;
+    let block_area = chunk_area as f64 * (grid_scale as f64) * (grid_scale as f64);
+
+    if chunk_area == 0 {
+        return TerritoryStats {
+            id: terr.id,
+            chunk_area: 0,
+            block_area: 0.0,
+            perimeter: 0,
+            components: 0,
+            holes: 0,
+            xmin: 0,
+            ymin: 0,
+            xmax: 0,
+            ymax: 0,
+            centroid_x: 0.0,
+            centroid_y: 0.0,
+            compactness: 0.0,
+            inaccessibility_radius: 0.0,
+        };
+    }
+
+    let aabb = terr.get_aabb();
+    let perimeter = perimeter(terr);
+
+    // holes of territory shape alone, other territories count as empty
+    let shape: FnvHashMap<Point<i32>, u32> = terr.coords.iter().map(|p| (*p, terr.id)).collect();
+    let holes = find_holes(&shape, &aabb).len() as u32;
+
+    let (sum_x, sum_y) = terr.coords.iter()
+        .fold((0.0, 0.0), |(sx, sy), p| (sx + p.x as f64, sy + p.y as f64));
+
+    let compactness = if perimeter > 0 {
+        4.0 * std::f64::consts::PI * chunk_area as f64 / (perimeter as f64 * perimeter as f64)
+    } else {
+        0.0
+    };
+
+    TerritoryStats {
+        id: terr.id,
+        chunk_area,
+        block_area,
+        perimeter,
+        components: terr.get_connected_components().len() as u32,
+        holes,
+        xmin: aabb.min.x,
+        ymin: aabb.min.y,
+        xmax: aabb.max.x,
+        ymax: aabb.max.y,
+        centroid_x: sum_x / chunk_area as f64,
+        centroid_y: sum_y / chunk_area as f64,
+        compactness,
+        inaccessibility_radius: inaccessibility_radius(terr, grid_scale).unwrap_or(f64::NAN),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn territory(coords: &[(i32, i32)]) -> Territory {
+        let mut terr = Territory::new(1);
+        for (x, y) in coords.iter() {
+            terr.coords.insert(Point::new(*x, *y));
+        }
+        terr
+    }
+
+    #[test]
+    fn square_stats() {
+        let coords: Vec<(i32, i32)> = (0..4).flat_map(|x| (0..4).map(move |y| (x, y))).collect();
+        let stats = territory_stats(&territory(&coords), 16);
+        assert_eq!(stats.chunk_area, 16);
+        assert_eq!(stats.block_area, 16.0 * 256.0);
+        assert_eq!(stats.perimeter, 16);
+        assert_eq!((stats.components, stats.holes), (1, 0));
+        assert_eq!((stats.xmin, stats.ymin, stats.xmax, stats.ymax), (0, 0, 3, 3));
+        assert_eq!((stats.centroid_x, stats.centroid_y), (1.5, 1.5));
+        assert!((stats.compactness - std::f64::consts::PI / 4.0).abs() < 1e-9);
+        // inscribed circle of 64 x 64 block square
+        assert!((stats.inaccessibility_radius - 32.0).abs() <= 1.0, "{}", stats.inaccessibility_radius);
+    }
+
+    #[test]
+    fn holes_and_components() {
+        // 3 x 3 ring and a separate chunk
+        let mut coords: Vec<(i32, i32)> = (0..3).flat_map(|x| (0..3).map(move |y| (x, y)))
+            .filter(|p| *p != (1, 1))
+            .collect();
+        coords.push((5, 0));
+        let stats = territory_stats(&territory(&coords), 16);
+        assert_eq!((stats.components, stats.holes), (2, 1));
+        assert_eq!(stats.perimeter, 12 + 4 + 4);
+
+        let empty = territory_stats(&territory(&[]), 16);
+        assert_eq!((empty.chunk_area, empty.perimeter, empty.components), (0, 0, 0));
+        assert_eq!(empty.inaccessibility_radius, 0.0);
+    }
+
+    #[test]
+    fn json_writes_nan_as_null() {
+        let mut stats = territory_stats(&territory(&[(0, 0)]), 16);
+        let json = stats.to_json();
+        assert_eq!(json["chunkArea"], Value::from(1));
+        assert_eq!(json["aabb"], Value::from(vec![0, 0, 0, 0]));
+        assert!(json["inaccessibilityRadius"].is_number());
+
+        stats.inaccessibility_radius = f64::NAN;
+        assert_eq!(stats.to_json()["inaccessibilityRadius"], Value::Null);
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::empty_line_after_doc_comments, clippy::len_zero, clippy::needless_return, clippy::redundant_field_names)]
+
 /// territory.rs
 /// ----------------------------------------------------------------
 /// Territory manipulation functionality
@@ -5,36 +7,19 @@
 /// https://en.wikipedia.org/wiki/Maze_solving_algorithm#Wall_follower
 
 extern crate bitflags;
-extern crate wasm_bindgen;
 
 use territory::geometry::{AABB, Point};
 use territory::polygon::get_core;
-use wasm_bindgen::prelude::*;
 use fnv::{FnvHashSet};
-
-#[wasm_bindgen]
-extern {
-    #[wasm_bindgen(js_namespace = console)]
-    fn log(msg: &str);
-
-    #[wasm_bindgen(js_namespace = console)]
-    fn error(msg: &str);
-}
-
-fn log1(s: &str) {
-    //log(s);
-}
-
-fn log2(s: &str) {
-    //log(s);
-}
+use serde_json::{Map, Value};
 
 pub struct Territory {
     pub id: u32,
     pub coords: FnvHashSet<Point<i32>>,
     pub neighbors: FnvHashSet<u32>,   // neighboring territory ids
     pub color: Option<u8>,            // color id, None if not assigned
-    pub is_at_edge: bool              // territory is at the edge (has coords that do not border territories)
+    pub is_at_edge: bool,             // territory is at the edge (has coords that do not border territories)
+    pub meta: Map<String, Value>,     // other world.json fields (name, core, nodes, ...), kept as raw json
 }
 
 impl Territory {
@@ -45,6 +30,7 @@ impl Territory {
             neighbors: FnvHashSet::default(),
             color: None,
             is_at_edge: false,
+            meta: Map::new(),
         }
     }
     
@@ -98,10 +84,17 @@ impl Territory {
     //    ...
     // ]
     pub fn get_border(&self, grid_scale: i32) -> Vec<i32> {
+        self.try_get_border(grid_scale).expect("Unable to find edge loop...")
+    }
+
+    // `get_border`, but returns None instead of panicking if an edge
+    // loop or core of the border cannot be found
+    #[allow(clippy::legacy_numeric_constants)]
+    pub fn try_get_border(&self, grid_scale: i32) -> Option<Vec<i32>> {
 
         // empty region
         if self.coords.len() == 0 {
-            return vec![0, 0, 0];
+            return Some(vec![0, 0, 0]);
         }
 
         // get bounding box of coords
@@ -118,13 +111,13 @@ impl Territory {
             if y > ymax { ymax = y };
         }
 
-        log1(&format!("{}, {}, {}, {}", xmin, xmax, ymin, ymax));
+        log_debug!("{}, {}, {}, {}", xmin, xmax, ymin, ymax);
 
         // grid, with zero padding on each side
         let size_x = (3 + xmax - xmin) as usize;
         let size_y = (3 + ymax - ymin) as usize;
         let mut grid = vec![vec![false; size_y]; size_x];
-        log1(&format!("grid[{}][{}]",size_x, size_y));
+        log_debug!("grid[{}][{}]",size_x, size_y);
 
         // set values in grid
         for chunk in self.coords.iter() {
@@ -269,7 +262,7 @@ impl Territory {
         let mut border_loops: Vec<Vec<Point<i32>>> = Vec::new();
 
         for c in clusters.iter() {
-            log1(&format!("CLUSTER {}", c.points.len()));
+            log_debug!("CLUSTER {}", c.points.len());
             let mut edges: Vec<Vec<Point<i32>>> = Vec::new();
             // clusters found by adjacent scanning so the points
             // are ordered: iteratively link and form edge loop
@@ -420,8 +413,7 @@ impl Territory {
 
             }
             else {
-                // this may be bad to have here...
-                panic!("Unable to find edge loop...");
+                return None;
             }
         }
 
@@ -430,10 +422,10 @@ impl Territory {
         //    this heuristic is okay
         let mut largest_loop_as_f32: Vec<Point<f32>> = Vec::new();
         for p in border_loops[largest_loop_index].iter() {
-            log2(&format!("{}, {}", p.x, p.y));
+            log_debug!("{}, {}", p.x, p.y);
             largest_loop_as_f32.push(Point::new(p.x as f32, p.y as f32));
         }
-        let core = get_core(&largest_loop_as_f32, 1.0).unwrap();
+        let core = get_core(&largest_loop_as_f32, 1.0).ok()?;
 
         // write output buffer
         let mut output_buffer = Vec::new();
@@ -441,7 +433,7 @@ impl Territory {
         output_buffer.push(grid_offset + (core.y as i32));
         output_buffer.push(clusters.len() as i32);
         for (c, l) in clusters.iter().zip(border_loops.iter()) {
-            log1("TEST!");
+            log_debug!("TEST!");
 
             output_buffer.push(c.points.len() as i32);
             output_buffer.push(l.len() as i32);
@@ -455,10 +447,11 @@ impl Territory {
             }
         }
 
-        return output_buffer;
+        return Some(output_buffer);
     }
 
     // return coords immediately neighboring this region
+    #[allow(clippy::legacy_numeric_constants)]
     pub fn get_neighboring_points(&self) -> FnvHashSet<Point<i32>> {
 
         // empty region
@@ -480,13 +473,13 @@ impl Territory {
             if y > ymax { ymax = y };
         }
 
-        log1(&format!("{}, {}, {}, {}", xmin, xmax, ymin, ymax));
+        log_debug!("{}, {}, {}, {}", xmin, xmax, ymin, ymax);
 
         // grid, with zero padding on each side
         let size_x = (3 + xmax - xmin) as usize;
         let size_y = (3 + ymax - ymin) as usize;
         let mut grid = vec![vec![false; size_y]; size_x];
-        log1(&format!("grid[{}][{}]",size_x, size_y));
+        log_debug!("grid[{}][{}]",size_x, size_y);
 
         // set values in grid to these coords
         for chunk in self.coords.iter() {
@@ -520,6 +513,90 @@ impl Territory {
         return neighbor_points;
     }
 
+    // return 4-connected clusters of coords in this territory,
+    // sorted from largest to smallest
+    pub fn get_connected_components(&self) -> Vec<Vec<Point<i32>>> {
+        connected_components(&self.coords)
+    }
+
+}
+
+// 4-neighborhood offsets, same adjacency as `get_neighboring_points`
+pub const NEIGHBORS_4: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+// separate a set of coords into 4-connected clusters using flood fill.
+// clusters sorted by size (largest first), ties broken by smallest
+// coord so output does not depend on hash set iteration order.
+pub fn connected_components(coords: &FnvHashSet<Point<i32>>) -> Vec<Vec<Point<i32>>> {
+    let mut visited: FnvHashSet<Point<i32>> = FnvHashSet::default();
+    let mut components: Vec<Vec<Point<i32>>> = Vec::new();
+
+    for start in coords.iter() {
+        if visited.contains(start) {
+            continue;
+        }
+
+        let mut component: Vec<Point<i32>> = Vec::new();
+        let mut stack: Vec<Point<i32>> = vec![*start];
+        visited.insert(*start);
+
+        while let Some(p) = stack.pop() {
+            component.push(p);
+            for (dx, dy) in NEIGHBORS_4.iter() {
+                let q = Point::new(p.x + dx, p.y + dy);
+                if coords.contains(&q) && !visited.contains(&q) {
+                    visited.insert(q);
+                    stack.push(q);
+                }
+            }
+        }
+
+        component.sort_by_key(|p| (p.x, p.y));
+        components.push(component);
+    }
+
+    components.sort_by(|a, b| b.len().cmp(&a.len()).then((a[0].x, a[0].y).cmp(&(b[0].x, b[0].y))));
+
+    return components;
+}
+
+// 8-neighborhood ring around a point, in circular order
+const RING_8: [(i32, i32); 8] = [(-1, -1), (0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0)];
+
+// check if removing p from coords keeps its 4-neighbors in coords
+// 4-connected to each other using only the 8 chunks around p.
+// locally connected means connectivity of the whole set is preserved,
+// (conservative: some removable points are rejected).
+pub fn is_simple_point(coords: &FnvHashSet<Point<i32>>, p: Point<i32>) -> bool {
+    let ring: Vec<Point<i32>> = RING_8.iter()
+        .map(|(dx, dy)| Point::new(p.x + dx, p.y + dy))
+        .filter(|q| coords.contains(q))
+        .collect();
+
+    let neighbors: Vec<Point<i32>> = NEIGHBORS_4.iter()
+        .map(|(dx, dy)| Point::new(p.x + dx, p.y + dy))
+        .filter(|q| coords.contains(q))
+        .collect();
+
+    // isolated point: removing it removes a component
+    if neighbors.is_empty() {
+        return false;
+    }
+
+    // flood fill ring from first 4-neighbor
+    let mut visited: Vec<Point<i32>> = vec![neighbors[0]];
+    let mut stack: Vec<Point<i32>> = vec![neighbors[0]];
+    while let Some(q) = stack.pop() {
+        for r in ring.iter() {
+            let adjacent = (r.x - q.x).abs() + (r.y - q.y).abs() == 1;
+            if adjacent && !visited.contains(r) {
+                visited.push(*r);
+                stack.push(*r);
+            }
+        }
+    }
+
+    neighbors.iter().all(|q| visited.contains(q))
 }
 
 bitflags! {
@@ -544,6 +621,7 @@ bitflags! {
 }
 
 // check if two points are adjacent in a grid
+#[allow(clippy::if_same_then_else, clippy::ptr_arg)]
 fn points_are_adjacent_border(p1: Point<i32>, p2: Point<i32>, grid: &Vec<Vec<bool>>, xmin: i32, ymin: i32) -> bool {
     // check immediate neighbors
     if p1.x == p2.x && ( p1.y == p2.y - 1 || p1.y == p2.y + 1 ) {
@@ -581,6 +659,7 @@ fn points_are_adjacent_border(p1: Point<i32>, p2: Point<i32>, grid: &Vec<Vec<boo
 //           insert edge_new into loops
 //           continue outer for loop
 //    edge1 has no possible joins, add to finished stack
+#[allow(clippy::clone_on_copy)]
 fn join_edge_loops(mut edge_loops: Vec<Vec<Point<i32>>>) -> Vec<Vec<Point<i32>>> {
 
     // list of loops without any more possible connections
@@ -0,0 +1,512 @@
+//! validate.rs
+//! ----------------------------------------------------------------
+//! World validation, finds issues that should be fixed before export:
+//!
+//! - emptyTerritory: territory with zero chunks
+//! - disconnected: territory with more than one 4-connected component,
+//!   chunks are the fragments outside the largest component
+//! - hole: unclaimed chunks fully enclosed by territories,
+//!   territories are the enclosing territory ids
+//! - diagonalJoin: two chunks of a territory touching only by a corner
+//!   (2x2 block with the other diagonal not owned by territory), where
+//!   the chunks are not 4-connected through the rest of the territory
+//! - sliver: single-chunk spikes, chunks with exactly one 4-neighbor
+//!   in the same territory
+//! - gridMismatch: chunk where `grid`, `grid_occupied_coords` and
+//!   `Territory::coords` disagree
+//! - belowMinSize: territory with fewer chunks than `min_size`
+//!
+//! Issues are returned sorted by kind, then territory ids.
+
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+use std::collections::VecDeque;
+use fnv::{FnvHashMap, FnvHashSet};
+use serde_json::{Map, Value};
+use territory::geometry::{AABB, Point};
+use territory::territory::{Territory, NEIGHBORS_4};
+
+/// World validation checks to run
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, Debug)]
+pub struct ValidationOptions {
+    // report territories with 0 < size < min_size (0 = disabled)
+    pub min_size: u32,
+    pub check_empty: bool,
+    pub check_disconnected: bool,
+    pub check_holes: bool,
+    pub check_diagonal_joins: bool,
+    pub check_slivers: bool,
+    pub check_grid: bool,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> ValidationOptions {
+        ValidationOptions::new()
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl ValidationOptions {
+    /// Default options, all checks enabled, no min size.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new() -> ValidationOptions {
+        ValidationOptions {
+            min_size: 0,
+            check_empty: true,
+            check_disconnected: true,
+            check_holes: true,
+            check_diagonal_joins: true,
+            check_slivers: true,
+            check_grid: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IssueKind {
+    GridMismatch,
+    EmptyTerritory,
+    Disconnected,
+    Hole,
+    DiagonalJoin,
+    Sliver,
+    BelowMinSize,
+}
+
+impl IssueKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IssueKind::GridMismatch => "gridMismatch",
+            IssueKind::EmptyTerritory => "emptyTerritory",
+            IssueKind::Disconnected => "disconnected",
+            IssueKind::Hole => "hole",
+            IssueKind::DiagonalJoin => "diagonalJoin",
+            IssueKind::Sliver => "sliver",
+            IssueKind::BelowMinSize => "belowMinSize",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub territories: Vec<u32>,
+    pub chunks: Vec<Point<i32>>,
+}
+
+impl Issue {
+    fn new(kind: IssueKind, territories: Vec<u32>, mut chunks: Vec<Point<i32>>) -> Issue {
+        chunks.sort_by_key(|p| (p.x, p.y));
+        Issue {
+            kind,
+            territories,
+            chunks,
+        }
+    }
+
+    // json object: { "kind": name, "territories": [id, ...], "chunks": [x1, y1, x2, y2, ...] }
+    pub fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("kind".to_string(), Value::from(self.kind.name()));
+        obj.insert("territories".to_string(), Value::from(self.territories.clone()));
+        let chunks: Vec<i32> = self.chunks.iter().flat_map(|p| vec![p.x, p.y]).collect();
+        obj.insert("chunks".to_string(), Value::from(chunks));
+        Value::Object(obj)
+    }
+}
+
+/// Unclaimed 4-connected region fully enclosed by territories
+pub struct Hole {
+    pub chunks: Vec<Point<i32>>,
+    // (enclosing territory id, shared border length in chunk edges), sorted by id
+    pub borders: Vec<(u32, u32)>,
+}
+
+impl Hole {
+    pub fn territories(&self) -> Vec<u32> {
+        self.borders.iter().map(|(id, _)| *id).collect()
+    }
+}
+
+/// Find unclaimed regions in grid inside aabb not connected to the
+/// outside (region outside aabb). Use grid chunk bounding box to find
+/// all holes in world, or a territory bounding box to find holes
+/// enclosed by that territory.
+///
+/// Regions are flood filled from unclaimed neighbors of claimed chunks
+/// inside aabb, a region is outside as soon as it leaves aabb or reaches
+/// a region already found outside, so empty space in aabb far from
+/// claimed chunks is not visited. Holes are sorted by first chunk.
+pub fn find_holes(grid: &FnvHashMap<Point<i32>, u32>, aabb: &AABB<i32>) -> Vec<Hole> {
+    // unclaimed chunks visited by any flood fill, and the subset in
+    // regions connected to the outside
+    let mut visited: FnvHashSet<Point<i32>> = FnvHashSet::default();
+    let mut outside: FnvHashSet<Point<i32>> = FnvHashSet::default();
+    let mut holes: Vec<Hole> = Vec::new();
+
+    let seeds = grid.keys()
+        .filter(|p| aabb.contains(p))
+        .flat_map(|p| NEIGHBORS_4.iter().map(move |(dx, dy)| Point::new(p.x + dx, p.y + dy)));
+
+    for start in seeds {
+        if !aabb.contains(&start) || grid.contains_key(&start) || visited.contains(&start) {
+            continue;
+        }
+
+        // breadth first so regions open to the outside stop close to start
+        let mut region: Vec<Point<i32>> = Vec::new();
+        let mut borders: FnvHashMap<u32, u32> = FnvHashMap::default();
+        let mut is_outside = false;
+        let mut queue: VecDeque<Point<i32>> = VecDeque::new();
+        queue.push_back(start);
+        visited.insert(start);
+
+        'fill: while let Some(p) = queue.pop_front() {
+            region.push(p);
+            for (dx, dy) in NEIGHBORS_4.iter() {
+                let q = Point::new(p.x + dx, p.y + dy);
+                if let Some(id) = grid.get(&q) {
+                    *borders.entry(*id).or_insert(0) += 1;
+                }
+                else if !aabb.contains(&q) || outside.contains(&q) {
+                    is_outside = true;
+                    break 'fill;
+                }
+                else if visited.insert(q) {
+                    queue.push_back(q);
+                }
+            }
+        }
+
+        if is_outside {
+            outside.extend(region);
+            outside.extend(queue);
+        }
+        else {
+            let mut borders: Vec<(u32, u32)> = borders.into_iter().collect();
+            borders.sort();
+            region.sort_by_key(|p| (p.x, p.y));
+            holes.push(Hole {
+                chunks: region,
+                borders,
+            });
+        }
+    }
+
+    holes.sort_by_key(|hole| (hole.chunks[0].x, hole.chunks[0].y));
+    holes
+}
+
+// chunks of territory touching another chunk of territory only by a
+// corner, where the two chunks are in different 4-connected components
+// (pieces joined only diagonally, not also 4-connected elsewhere)
+fn find_diagonal_joins(coords: &FnvHashSet<Point<i32>>) -> Vec<Point<i32>> {
+    let mut pairs: Vec<(Point<i32>, Point<i32>)> = Vec::new();
+    for p in coords.iter() {
+        // check diagonals (1, 1) and (1, -1) so each pair is visited once
+        for dy in [-1, 1].iter() {
+            let q = Point::new(p.x + 1, p.y + dy);
+            let side1 = Point::new(p.x + 1, p.y);
+            let side2 = Point::new(p.x, p.y + dy);
+            if coords.contains(&q) && !coords.contains(&side1) && !coords.contains(&side2) {
+                pairs.push((*p, q));
+            }
+        }
+    }
+    if pairs.is_empty() {
+        return Vec::new();
+    }
+
+    // label 4-connected components
+    let mut component: FnvHashMap<Point<i32>, usize> = FnvHashMap::default();
+    let mut label = 0;
+    for start in coords.iter() {
+        if component.contains_key(start) {
+            continue;
+        }
+        component.insert(*start, label);
+        let mut queue: VecDeque<Point<i32>> = VecDeque::new();
+        queue.push_back(*start);
+        while let Some(p) = queue.pop_front() {
+            for (dx, dy) in NEIGHBORS_4.iter() {
+                let q = Point::new(p.x + dx, p.y + dy);
+                if coords.contains(&q) && !component.contains_key(&q) {
+                    component.insert(q, label);
+                    queue.push_back(q);
+                }
+            }
+        }
+        label += 1;
+    }
+
+    let mut chunks: FnvHashSet<Point<i32>> = FnvHashSet::default();
+    for (p, q) in pairs.into_iter() {
+        if component[&p] != component[&q] {
+            chunks.insert(p);
+            chunks.insert(q);
+        }
+    }
+    chunks.into_iter().collect()
+}
+
+// chunks with exactly one 4-neighbor in the same territory
+fn find_slivers(coords: &FnvHashSet<Point<i32>>) -> Vec<Point<i32>> {
+    coords.iter()
+        .filter(|p| {
+            let count = NEIGHBORS_4.iter()
+                .filter(|(dx, dy)| coords.contains(&Point::new(p.x + dx, p.y + dy)))
+                .count();
+            count == 1
+        })
+        .cloned()
+        .collect()
+}
+
+// chunks where grid, occupied coords and territory coords disagree,
+// with the territory ids involved
+fn find_grid_mismatches(
+    grid: &FnvHashMap<Point<i32>, u32>,
+    grid_occupied_coords: &FnvHashSet<Point<i32>>,
+    territories: &FnvHashMap<u32, Territory>,
+) -> Vec<Issue> {
+    let mut mismatches: FnvHashMap<Point<i32>, FnvHashSet<u32>> = FnvHashMap::default();
+
+    for (p, id) in grid.iter() {
+        let in_territory = territories.get(id).is_some_and(|terr| terr.coords.contains(p));
+        if !in_territory || !grid_occupied_coords.contains(p) {
+            mismatches.entry(*p).or_default().insert(*id);
+        }
+    }
+    for p in grid_occupied_coords.iter() {
+        if !grid.contains_key(p) {
+            mismatches.entry(*p).or_default();
+        }
+    }
+    for (id, terr) in territories.iter() {
+        for p in terr.coords.iter() {
+            if grid.get(p) != Some(id) {
+                let ids = mismatches.entry(*p).or_default();
+                ids.insert(*id);
+                if let Some(owner) = grid.get(p) {
+                    ids.insert(*owner);
+                }
+            }
+        }
+    }
+
+    mismatches.into_iter()
+        .map(|(p, ids)| {
+            let mut ids: Vec<u32> = ids.into_iter().collect();
+            ids.sort();
+            Issue::new(IssueKind::GridMismatch, ids, vec![p])
+        })
+        .collect()
+}
+
+/// Run validation checks on world data, returns issues sorted
+/// by kind, territory ids, then chunks.
+pub fn validate_world(
+    grid: &FnvHashMap<Point<i32>, u32>,
+    grid_occupied_coords: &FnvHashSet<Point<i32>>,
+    territories: &FnvHashMap<u32, Territory>,
+    options: &ValidationOptions,
+) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = Vec::new();
+
+    if options.check_grid {
+        issues.extend(find_grid_mismatches(grid, grid_occupied_coords, territories));
+    }
+
+    for (id, terr) in territories.iter() {
+        let size = terr.coords.len();
+
+        if size == 0 {
+            if options.check_empty {
+                issues.push(Issue::new(IssueKind::EmptyTerritory, vec![*id], Vec::new()));
+            }
+            continue;
+        }
+
+        if options.check_disconnected {
+            let components = terr.get_connected_components();
+            if components.len() > 1 {
+                let fragments: Vec<Point<i32>> = components.into_iter().skip(1).flatten().collect();
+                issues.push(Issue::new(IssueKind::Disconnected, vec![*id], fragments));
+            }
+        }
+
+        if options.check_diagonal_joins {
+            let chunks = find_diagonal_joins(&terr.coords);
+            if !chunks.is_empty() {
+                issues.push(Issue::new(IssueKind::DiagonalJoin, vec![*id], chunks));
+            }
+        }
+
+        if options.check_slivers {
+            let chunks = find_slivers(&terr.coords);
+            if !chunks.is_empty() {
+                issues.push(Issue::new(IssueKind::Sliver, vec![*id], chunks));
+            }
+        }
+
+        if (size as u32) < options.min_size {
+            issues.push(Issue::new(IssueKind::BelowMinSize, vec![*id], Vec::new()));
+        }
+    }
+
+    if options.check_holes && !grid.is_empty() {
+        let aabb = AABB::from_points(grid.keys().cloned());
+        for hole in find_holes(grid, &aabb).into_iter() {
+            issues.push(Issue::new(IssueKind::Hole, hole.territories(), hole.chunks));
+        }
+    }
+
+    issues.sort_by(|a, b| {
+        a.kind.cmp(&b.kind)
+            .then_with(|| a.territories.cmp(&b.territories))
+            .then_with(|| a.chunks.first().map(|p| (p.x, p.y)).cmp(&b.chunks.first().map(|p| (p.x, p.y))))
+    });
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+
+    // unclaimed chunks in aabb not connected to chunks outside aabb
+    fn brute_force_holes(grid: &FnvHashMap<Point<i32>, u32>, aabb: &AABB<i32>) -> FnvHashSet<Point<i32>> {
+        let mut reached: FnvHashSet<Point<i32>> = FnvHashSet::default();
+        let mut queue: VecDeque<Point<i32>> = VecDeque::new();
+        for x in aabb.min.x..=aabb.max.x {
+            for y in aabb.min.y..=aabb.max.y {
+                let p = Point::new(x, y);
+                let on_border = x == aabb.min.x || x == aabb.max.x || y == aabb.min.y || y == aabb.max.y;
+                if on_border && !grid.contains_key(&p) && reached.insert(p) {
+                    queue.push_back(p);
+                }
+            }
+        }
+        while let Some(p) = queue.pop_front() {
+            for (dx, dy) in NEIGHBORS_4.iter() {
+                let q = Point::new(p.x + dx, p.y + dy);
+                if aabb.contains(&q) && !grid.contains_key(&q) && reached.insert(q) {
+                    queue.push_back(q);
+                }
+            }
+        }
+
+        let mut holes = FnvHashSet::default();
+        for x in aabb.min.x..=aabb.max.x {
+            for y in aabb.min.y..=aabb.max.y {
+                let p = Point::new(x, y);
+                if !grid.contains_key(&p) && !reached.contains(&p) {
+                    holes.insert(p);
+                }
+            }
+        }
+        holes
+    }
+
+    #[test]
+    fn find_holes_matches_brute_force() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        for _ in 0..300 {
+            let (w, h) = (rng.gen_range(1..14), rng.gen_range(1..14));
+            let density = rng.gen_range(0.3..0.9);
+            let mut grid: FnvHashMap<Point<i32>, u32> = FnvHashMap::default();
+            for x in 0..w {
+                for y in 0..h {
+                    if rng.gen_bool(density) {
+                        grid.insert(Point::new(x, y), rng.gen_range(0..3));
+                    }
+                }
+            }
+            let aabb = AABB::new(Point::new(0, 0), Point::new(w - 1, h - 1));
+
+            let holes = find_holes(&grid, &aabb);
+            let chunks: FnvHashSet<Point<i32>> = holes.iter().flat_map(|hole| hole.chunks.iter().cloned()).collect();
+            assert_eq!(chunks.len(), holes.iter().map(|hole| hole.chunks.len()).sum::<usize>());
+            assert_eq!(chunks, brute_force_holes(&grid, &aabb));
+        }
+    }
+
+    #[test]
+    fn find_holes_borders() {
+        // 3 x 3 ring of territory 1 around unclaimed center, territory 2
+        // replaces the top middle chunk
+        let mut grid: FnvHashMap<Point<i32>, u32> = FnvHashMap::default();
+        for x in 0..3 {
+            for y in 0..3 {
+                if (x, y) != (1, 1) {
+                    grid.insert(Point::new(x, y), 1);
+                }
+            }
+        }
+        grid.insert(Point::new(1, 2), 2);
+
+        let holes = find_holes(&grid, &AABB::new(Point::new(0, 0), Point::new(2, 2)));
+        assert_eq!(holes.len(), 1);
+        assert_eq!(holes[0].chunks, vec![Point::new(1, 1)]);
+        assert_eq!(holes[0].borders, vec![(1, 3), (2, 1)]);
+        assert_eq!(holes[0].territories(), vec![1, 2]);
+
+        // open ring has no holes
+        grid.remove(&Point::new(1, 0));
+        assert!(find_holes(&grid, &AABB::new(Point::new(0, 0), Point::new(2, 2))).is_empty());
+    }
+
+    #[test]
+    fn validate_world_issue_kinds() {
+        let mut grid: FnvHashMap<Point<i32>, u32> = FnvHashMap::default();
+        let mut territories: FnvHashMap<u32, Territory> = FnvHashMap::default();
+        let mut claim = |id: u32, x: i32, y: i32| {
+            grid.insert(Point::new(x, y), id);
+            territories.entry(id).or_insert_with(|| Territory::new(id)).coords.insert(Point::new(x, y));
+        };
+
+        // territory 1: 2 x 2 block with a diagonal fragment
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1), (2, 2)].iter() {
+            claim(1, *x, *y);
+        }
+        // territory 2: 3 x 3 block with a one chunk spike
+        for x in 10..13 {
+            for y in 0..3 {
+                claim(2, x, y);
+            }
+        }
+        claim(2, 13, 1);
+        territories.insert(3, Territory::new(3));
+
+        let occupied: FnvHashSet<Point<i32>> = grid.keys().cloned().collect();
+        let mut options = ValidationOptions::new();
+        options.min_size = 6;
+        let issues = validate_world(&grid, &occupied, &territories, &options);
+
+        let kinds: Vec<(&str, Vec<u32>)> = issues.iter().map(|i| (i.kind.name(), i.territories.clone())).collect();
+        assert!(kinds.contains(&("emptyTerritory", vec![3])));
+        assert!(kinds.contains(&("disconnected", vec![1])));
+        assert!(kinds.contains(&("diagonalJoin", vec![1])));
+        assert!(kinds.contains(&("sliver", vec![2])));
+        assert!(kinds.contains(&("belowMinSize", vec![1])));
+        assert!(!kinds.iter().any(|(kind, _)| *kind == "gridMismatch"));
+        assert!(!kinds.iter().any(|(kind, _)| *kind == "hole"));
+    }
+
+    #[test]
+    fn diagonal_join_only_between_components() {
+        // corner touch between two separate pieces
+        let mut coords: FnvHashSet<Point<i32>> = [(0, 0), (1, 1)].iter().map(|(x, y)| Point::new(*x, *y)).collect();
+        let mut joins = find_diagonal_joins(&coords);
+        joins.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(joins, vec![Point::new(0, 0), Point::new(1, 1)]);
+
+        // same corner touch, but the chunks are 4-connected around (2, 0)
+        coords.extend([(0, -1), (1, -1), (2, -1), (2, 0), (2, 1)].iter().map(|(x, y)| Point::new(*x, *y)));
+        assert!(find_diagonal_joins(&coords).is_empty());
+    }
+}
@@ -1,36 +1,117 @@
-extern crate wasm_bindgen;
+#![allow(clippy::len_zero, clippy::needless_return, clippy::redundant_field_names)]
+
 extern crate fnv;
 
-use std::collections::{BTreeMap};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
 use std::collections::btree_map::Entry;
+use std::convert::TryFrom;
 use std::iter::FromIterator;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 use fnv::{FnvHashMap, FnvHashSet};
-use territory::territory::{Territory};
-use territory::geometry::Point;
-use territory::generator::{CellDiagram, generate_random_cells};
+use serde_json::{Map, Value};
+use thiserror::Error;
+use territory::adjacency::Adjacency;
+use territory::territory::{Territory, NEIGHBORS_4, is_simple_point};
+use territory::geometry::{AABB, Point};
+use territory::graph::{graph_to_dot, graph_to_json, shortest_path, subset_components, within_hops};
+use territory::diff::{WorldDiff, compute_diff};
+use territory::distance::{DistanceField, MAX_UNCLAIMED_DISTANCE, distance_field};
+use territory::merge::{MergeResult, merge_diffs};
+use territory::generator::{CellDiagram, GeneratorOptions, generate_random_cells};
+use territory::history::{Change, History};
+use territory::raster::{points_from_buffer, rasterize_polygon, rasterize_polyline, rasterize_rect};
+use territory::snapshot::{SnapshotReader, SnapshotWriter};
+use territory::spatial::SpatialIndex;
+use territory::stats::{TerritoryStats, territory_stats};
+use territory::validate::{ValidationOptions, find_holes, validate_world};
+use territory::world_json::{default_world_meta, territory_from_json, territory_to_json};
+
+// max territory node colors
+pub const MAX_COLORS: usize = 5;
+
+// max number of values in an ownership raster (64 MB buffer)
+const MAX_RASTER_SIZE: u64 = 1 << 24;
 
-#[wasm_bindgen]
-extern {
-    #[wasm_bindgen(js_namespace = console)]
-    fn log(msg: &str);
+// max number of chunks in a region bounds for generating territories
+const MAX_GENERATE_REGION_SIZE: u64 = 1 << 22;
 
-    #[wasm_bindgen(js_namespace = console)]
-    fn error(msg: &str);
+// size rebalance move: (sort key, chunk, receiver territory)
+type RebalanceCandidate = ((i64, i64, i32, i32), Point<i32>, u32);
+
+/// Territory graph coloring algorithms
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColoringAlgorithm {
+    // smallest degree last greedy coloring, up to 6 colors
+    Greedy6 = 0,
+    // linear time planar 5-coloring, up to MAX_COLORS colors
+    Planar5 = 1,
 }
 
-fn print1(s: &str) {
-    // log(s);
+/// Strategies for choosing which neighbor a small territory merges into
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeStrategy {
+    // neighbor with fewest chunks
+    SmallestNeighbor = 0,
+    // neighbor sharing longest border
+    LongestBorder = 1,
+    // neighbor giving most compact merged territory (Polsby-Popper)
+    MostCompact = 2,
 }
 
-fn print2(s: &str) {
-    log(s);
+/// Possible World errors
+#[derive(Error, Debug, PartialEq)]
+pub enum WorldError {
+    #[error("Invalid world json: {0}")]
+    InvalidJson(String),
+    #[error("Invalid world snapshot: {0}")]
+    InvalidSnapshot(String),
+    #[error("Diff does not apply, chunk ({0}, {1}) owner changed")]
+    DiffChunkConflict(i32, i32),
+    #[error("Diff does not apply, territory {0} already exists")]
+    DiffTerritoryExists(u32),
+    #[error("Diff does not apply, deleted territory {0} owns chunks not moved by diff")]
+    DiffTerritoryNotEmpty(u32),
+    #[error("Territory {0} does not exist")]
+    TerritoryNotFound(u32),
+    #[error("Territory {0} already exists")]
+    TerritoryExists(u32),
+    #[error("Territory id {0} out of range")]
+    TerritoryIdOutOfRange(u32),
+    #[error("Chunk ({0}, {1}) is already claimed")]
+    ChunkClaimed(i32, i32),
+    #[error("Invalid grid scale {0}, must be positive")]
+    InvalidGridScale(i32),
+    #[error("Invalid bounds, expected [xmin, ymin, xmax, ymax]")]
+    InvalidBounds,
+    #[error("Distance {0} over unclaimed chunks exceeds max {1}, use claimed chunks only")]
+    DistanceTooLarge(u32, u32),
+    #[error("Invalid raster downsample factor {0}")]
+    InvalidDownsample(u32),
+    #[error("Raster size {0} exceeds max {1} values")]
+    RasterTooLarge(u64, u64),
+    #[error("Region size {0} exceeds max {1} chunks")]
+    RegionTooLarge(u64, u64),
+    #[error("Flood fill region is not enclosed within bounds")]
+    FloodFillUnbounded,
+    #[error("Flood fill region exceeds {0} chunks")]
+    FloodFillTooLarge(u32),
+    #[error("Invalid shape, coords must be finite and within chunk coord range")]
+    InvalidShape,
 }
 
-// max territory node colors
-const MAX_COLORS: usize = 5;
+/// Chunk owner encoding in u32 buffers returned to js (batch lookups,
+/// ownership rasters): territory id + 1, 0 = unclaimed. Territory ids
+/// are below u32 max (id counter must stay above every id), so every
+/// id has a distinct value.
+pub fn encode_owner(owner: Option<u32>) -> u32 {
+    owner.map_or(0, |id| id.saturating_add(1))
+}
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub struct World {
     // grid maps each coord -> territory id
     grid: FnvHashMap<Point<i32>, u32>,
@@ -42,42 +123,236 @@ pub struct World {
     territories: FnvHashMap<u32, Territory>,
     // id counter for territories
     territory_id_counter: u32,
+    // undo/redo log of world edits
+    history: History,
+    // spatial index of chunks and territory bounds, updated on every edit
+    index: SpatialIndex,
+    // shared border counts between territories, updated on every edit
+    adjacency: Adjacency,
+    // other top level world.json fields (meta, nodes, ...), kept as raw json
+    json_fields: Map<String, Value>,
 }
 
 // rust internal functions
 impl World {
-    pub fn add_territory(&mut self, terr: Territory) {
-        for p in terr.coords.iter() {
-            self.grid_occupied_coords.insert(*p);
-            self.grid.insert(*p, terr.id);
+    /// Set owner of a chunk (None = unclaimed) in world grid and in
+    /// territory coords, without recording history. Owner territory
+    /// must exist. Returns previous owner of the chunk.
+    fn set_chunk_owner_unrecorded(&mut self, p: Point<i32>, owner: Option<u32>) -> Option<u32> {
+        let prev = if let Some(id) = owner {
+            self.grid_occupied_coords.insert(p);
+            self.grid.insert(p, id)
+        } else {
+            self.grid_occupied_coords.remove(&p);
+            self.grid.remove(&p)
+        };
+
+        if prev != owner {
+            if let Some(prev_id) = prev {
+                if let Some(territory) = self.territories.get_mut(&prev_id) {
+                    territory.coords.remove(&p);
+                }
+                self.index.remove(p, prev_id);
+            }
+            if let Some(id) = owner {
+                if let Some(territory) = self.territories.get_mut(&id) {
+                    territory.coords.insert(p);
+                }
+                self.index.insert(p, id);
+            }
+            self.adjacency.update_chunk(p, prev, owner, &self.grid);
         }
 
-        self.territories.insert(terr.id, terr);
+        prev
     }
 
-    pub fn remove_territory(&mut self, id: u32) -> Option<Territory> {
-        // remove territory and its chunks from world grid
-        if let Some(territory) = self.territories.remove(&id) {
-            let chunks = &territory.coords;
-            for p in chunks.iter() {
-                self.grid_occupied_coords.remove(&p);
-                self.grid.remove(&p);
+    /// Set owner of a chunk and record change in history.
+    /// Returns previous owner of the chunk.
+    fn set_chunk_owner(&mut self, p: Point<i32>, owner: Option<u32>) -> Option<u32> {
+        let prev = self.set_chunk_owner_unrecorded(p, owner);
+        if prev != owner {
+            self.history.record(Change::Chunk {
+                p: p,
+                prev: prev,
+                next: owner,
+            });
+        }
+        prev
+    }
+
+    /// Set id counter and record change in history.
+    fn set_id_counter(&mut self, count: u32) {
+        if count != self.territory_id_counter {
+            self.history.record(Change::IdCounter {
+                prev: self.territory_id_counter,
+                next: count,
+            });
+            self.territory_id_counter = count;
+        }
+    }
+
+    /// Raise id counter above id (recorded), so new ids never collide
+    /// with id. Fails for u32 max, counter cannot go above it.
+    fn reserve_id(&mut self, id: u32) -> Result<(), WorldError> {
+        let next_id = id.checked_add(1).ok_or(WorldError::TerritoryIdOutOfRange(id))?;
+        if next_id > self.territory_id_counter {
+            self.set_id_counter(next_id);
+        }
+        Ok(())
+    }
+
+    /// Insert new territory without chunks and record in history.
+    /// Existing territory with same id is kept, returns false.
+    fn insert_territory(&mut self, id: u32, color: Option<u8>, meta: Map<String, Value>) -> bool {
+        if self.territories.contains_key(&id) {
+            return false;
+        }
+        let mut territory = Territory::new(id);
+        territory.color = color;
+        territory.meta = meta.clone();
+        self.territories.insert(id, territory);
+        self.history.record(Change::CreateTerritory {
+            id: id,
+            color: color,
+            meta: Box::new(meta),
+        });
+        true
+    }
+
+    fn insert_empty_territory(&mut self, id: u32) -> bool {
+        self.insert_territory(id, None, Map::new())
+    }
+
+    /// Apply change to world without recording history.
+    /// If `invert`, applies the inverse change (for undo).
+    fn apply_change(&mut self, change: &Change, invert: bool) {
+        match *change {
+            Change::Chunk { p, prev, next } => {
+                let owner = if invert { prev } else { next };
+                self.set_chunk_owner_unrecorded(p, owner);
+            },
+            Change::CreateTerritory { id, color, ref meta } => {
+                if invert {
+                    self.territories.remove(&id);
+                } else {
+                    let mut territory = Territory::new(id);
+                    territory.color = color;
+                    territory.meta = (**meta).clone();
+                    self.territories.insert(id, territory);
+                }
+            },
+            Change::RemoveTerritory { id, color, ref meta } => {
+                if invert {
+                    let mut territory = Territory::new(id);
+                    territory.color = color;
+                    territory.meta = (**meta).clone();
+                    self.territories.insert(id, territory);
+                } else {
+                    self.territories.remove(&id);
+                }
+            },
+            Change::IdCounter { prev, next } => {
+                self.territory_id_counter = if invert { prev } else { next };
+            },
+        }
+    }
+
+    /// Add territory with its chunks as one undo step, replaces existing
+    /// territory with same id. Id counter is moved past the id so new
+    /// territories do not reuse it.
+    pub fn add_territory(&mut self, mut terr: Territory) -> Result<(), WorldError> {
+        let id = terr.id;
+        let coords = std::mem::take(&mut terr.coords);
+        self.history.begin_group();
+        let result = self.reserve_id(id);
+        if result.is_ok() {
+            self.remove_territory(id);
+            self.insert_territory(id, terr.color, terr.meta);
+            if let Some(territory) = self.territories.get_mut(&id) {
+                territory.neighbors = terr.neighbors;
+                territory.is_at_edge = terr.is_at_edge;
+            }
+            for p in coords.into_iter() {
+                self.set_chunk_owner(p, Some(id));
             }
+        }
+        self.history.end_group();
 
-            return Some(territory);
+        result
+    }
+
+    pub fn remove_territory(&mut self, id: u32) -> Option<Territory> {
+        // remove territory chunks from world grid, then remove the
+        // empty territory. returned territory keeps its coords.
+        let coords: Vec<Point<i32>> = self.territories.get(&id)?.coords.iter().cloned().collect();
+        for p in coords.iter() {
+            self.set_chunk_owner(*p, None);
         }
 
-        None
+        let mut territory = self.territories.remove(&id)?;
+        self.history.record(Change::RemoveTerritory {
+            id: id,
+            color: territory.color,
+            meta: Box::new(territory.meta.clone()),
+        });
+        territory.coords.extend(coords);
+
+        Some(territory)
     }
 
     pub fn add_points_to_territory<T: IntoIterator<Item = Point<i32>>>(&mut self, id: u32, coords: T) -> bool {
         // insert coords into territory and to grid, overwrites any previous territory coords
-        if let Some(territory) = self.territories.get_mut(&id) {
+        if self.territories.contains_key(&id) {
             for p in coords.into_iter() {
-                self.grid_occupied_coords.insert(p);
-                self.grid.insert(p, id);
-                territory.coords.insert(p);
+                self.set_chunk_owner(p, Some(id));
+            }
+            return true;
+        }
+
+        return false;
+    }
+
+    // territory id -> number of chunks, for graph export
+    fn territory_sizes(&self) -> FnvHashMap<u32, usize> {
+        self.territories.iter().map(|(id, terr)| (*id, terr.coords.len())).collect()
+    }
+
+    /// Add chunks not occupied by any territory to territory id,
+    /// as one history step. Returns true if any chunk added.
+    fn add_unoccupied_chunks(&mut self, id: u32, chunks: Vec<Point<i32>>) -> bool {
+        if !self.territories.contains_key(&id) {
+            return false;
+        }
+
+        let unoccupied: Vec<Point<i32>> = chunks.into_iter()
+            .filter(|p| !self.grid_occupied_coords.contains(p))
+            .collect();
+
+        if unoccupied.len() > 0 {
+            self.history.begin_group();
+            for p in unoccupied.into_iter() {
+                self.set_chunk_owner(p, Some(id));
+            }
+            self.history.end_group();
+            return true;
+        }
+
+        return false;
+    }
+
+    /// Remove chunks owned by territory id, as one history step.
+    /// Returns true if any chunk removed.
+    fn remove_territory_chunks(&mut self, id: u32, chunks: Vec<Point<i32>>) -> bool {
+        let owned: Vec<Point<i32>> = chunks.into_iter()
+            .filter(|p| self.grid.get(p) == Some(&id))
+            .collect();
+
+        if owned.len() > 0 {
+            self.history.begin_group();
+            for p in owned.into_iter() {
+                self.set_chunk_owner(p, None);
             }
+            self.history.end_group();
             return true;
         }
 
@@ -86,79 +361,256 @@ impl World {
 
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl World {
-    #[wasm_bindgen(constructor)]
+    /// Create empty world, None if grid_scale (blocks per chunk) is not positive.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
     pub fn new(grid_scale: i32) -> Option<World> {
+        if grid_scale <= 0 {
+            return None;
+        }
         Some(World {
             grid: FnvHashMap::default(),
             grid_occupied_coords: FnvHashSet::default(),
             grid_scale: grid_scale,
             territories: FnvHashMap::default(),
             territory_id_counter: 0,
+            history: History::new(),
+            index: SpatialIndex::new(),
+            adjacency: Adjacency::new(),
+            json_fields: Map::new(),
         })
     }
 
-    // delete world
-    #[wasm_bindgen]
+    /// Create world from a world.json string. Territory chunks, neighbors,
+    /// edge flags and colors are loaded into the world. All other fields
+    /// are preserved and written back by `toWorldJson`. If a chunk is
+    /// listed in multiple territories, the territory with larger id owns it.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=fromWorldJson))]
+    pub fn from_world_json(json: &str, grid_scale: i32) -> Result<World, WorldError> {
+        let mut fields: Map<String, Value> = match serde_json::from_str(json) {
+            Ok(Value::Object(fields)) => fields,
+            Ok(_) => return Err(WorldError::InvalidJson("expected json object".to_string())),
+            Err(err) => return Err(WorldError::InvalidJson(err.to_string())),
+        };
+
+        let mut territories: Vec<Territory> = Vec::new();
+        match fields.remove("territories") {
+            Some(Value::Object(entries)) => {
+                let mut ids: FnvHashSet<u32> = FnvHashSet::default();
+                for (key, value) in entries.iter() {
+                    let id = key.parse::<u32>()
+                        .map_err(|_| WorldError::InvalidJson(format!("invalid territory id \"{}\"", key)))?;
+                    // keys like "1" and "01" parse to same id
+                    if !ids.insert(id) {
+                        return Err(WorldError::InvalidJson(format!("duplicate territory id {}", id)));
+                    }
+                    territories.push(territory_from_json(id, value)?);
+                }
+            },
+            None | Some(Value::Null) => (),
+            Some(_) => return Err(WorldError::InvalidJson("invalid \"territories\"".to_string())),
+        }
+
+        // insert in id order, later territories overwrite chunks of earlier ones
+        territories.sort_by_key(|t| t.id);
+
+        let mut world = World::new(grid_scale).ok_or(WorldError::InvalidGridScale(grid_scale))?;
+        for mut terr in territories.into_iter() {
+            let id = terr.id;
+            let coords = std::mem::take(&mut terr.coords);
+            world.territories.insert(id, terr);
+            for p in coords.into_iter() {
+                world.set_chunk_owner_unrecorded(p, Some(id));
+            }
+            // id counter must stay above all ids
+            let next_id = id.checked_add(1)
+                .ok_or_else(|| WorldError::InvalidJson(format!("territory id {} out of range", id)))?;
+            world.territory_id_counter = world.territory_id_counter.max(next_id);
+        }
+        world.json_fields = fields;
+
+        Ok(world)
+    }
+
+    /// Serialize world into a world.json string. Empty territories are
+    /// skipped. Neighbors, edge flags and colors are written as currently
+    /// stored, run `calculateNeighbors` and `generateColors` first to
+    /// update them.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=toWorldJson))]
+    pub fn to_world_json(&self) -> String {
+        let mut fields = self.json_fields.clone();
+        fields.entry("meta").or_insert_with(default_world_meta);
+
+        let mut ids: Vec<&u32> = self.territories.keys().collect();
+        ids.sort();
+
+        let mut territories = Map::new();
+        for id in ids.into_iter() {
+            let terr = &self.territories[id];
+            if terr.coords.len() > 0 {
+                territories.insert(id.to_string(), territory_to_json(terr));
+            }
+        }
+        fields.insert("territories".to_string(), Value::Object(territories));
+
+        Value::Object(fields).to_string()
+    }
+
+    /// Serialize world into compact binary snapshot (see `snapshot.rs`),
+    /// includes empty territories, id counter and colors. History,
+    /// neighbors and edge flags are not stored.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=toBytes))]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new();
+        writer.write_zigzag(self.grid_scale as i64);
+        writer.write_varint(self.territory_id_counter as u64);
+        writer.write_str(&Value::Object(self.json_fields.clone()).to_string());
+
+        let mut ids: Vec<&u32> = self.territories.keys().collect();
+        ids.sort();
+
+        writer.write_varint(ids.len() as u64);
+        for id in ids.into_iter() {
+            let terr = &self.territories[id];
+            writer.write_varint(*id as u64);
+            writer.write_varint(terr.color.map_or(0, |c| c as u64 + 1));
+            if terr.meta.is_empty() {
+                writer.write_str("");
+            } else {
+                writer.write_str(&Value::Object(terr.meta.clone()).to_string());
+            }
+            writer.write_chunks(&terr.coords);
+        }
+
+        writer.into_bytes()
+    }
+
+    /// Load world from binary snapshot created by `toBytes`.
+    /// Neighbors and edge flags are recalculated.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=fromBytes))]
+    pub fn from_bytes(bytes: &[u8]) -> Result<World, WorldError> {
+        fn parse_object(json: &str) -> Result<Map<String, Value>, WorldError> {
+            if json.is_empty() {
+                return Ok(Map::new());
+            }
+            match serde_json::from_str(json) {
+                Ok(Value::Object(fields)) => Ok(fields),
+                _ => Err(WorldError::InvalidSnapshot("invalid json fields".to_string())),
+            }
+        }
+
+        let mut reader = SnapshotReader::new(bytes)?;
+        let grid_scale = reader.read_i32()?;
+        let territory_id_counter = reader.read_u32()?;
+
+        let mut world = World::new(grid_scale).ok_or(WorldError::InvalidGridScale(grid_scale))?;
+        world.json_fields = parse_object(reader.read_str()?)?;
+
+        let num_territories = reader.read_varint()?;
+        let mut prev_id: Option<u32> = None;
+        for _ in 0..num_territories {
+            let id = reader.read_u32()?;
+            // sorted and unique, so a duplicate id cannot merge territories
+            if prev_id.is_some_and(|prev| id <= prev) {
+                return Err(WorldError::InvalidSnapshot(format!("territory id {} not in increasing order", id)));
+            }
+            prev_id = Some(id);
+            let color = match reader.read_varint()? {
+                0 => None,
+                c => Some(u8::try_from(c - 1).map_err(|_| WorldError::InvalidSnapshot(format!("territory {}: invalid color", id)))?),
+            };
+            let meta = parse_object(reader.read_str()?)?;
+            let chunks = reader.read_chunks()?;
+
+            let mut terr = Territory::new(id);
+            terr.color = color;
+            terr.meta = meta;
+            world.territories.insert(id, terr);
+            for p in chunks.into_iter() {
+                world.set_chunk_owner_unrecorded(p, Some(id));
+            }
+            let next_id = id.checked_add(1)
+                .ok_or_else(|| WorldError::InvalidSnapshot(format!("territory id {} out of range", id)))?;
+            world.territory_id_counter = world.territory_id_counter.max(next_id);
+        }
+
+        if !reader.is_at_end() {
+            return Err(WorldError::InvalidSnapshot("unexpected trailing data".to_string()));
+        }
+
+        world.territory_id_counter = world.territory_id_counter.max(territory_id_counter);
+        world.update_neighbors();
+
+        Ok(world)
+    }
+
+    // delete world, also clears undo/redo history
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn clear(&mut self) {
         self.grid.clear();
         self.grid_occupied_coords.clear();
         self.territories.clear();
+        self.history.clear();
+        self.index.clear();
+        self.adjacency.clear();
+        self.json_fields.clear();
+        self.territory_id_counter = 0;
     }
 
-    #[wasm_bindgen(js_name=getTerritoryIdCounter)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryIdCounter))]
     pub fn get_territory_id_counter(&self) -> u32 {
         self.territory_id_counter
     }
 
-    #[wasm_bindgen(js_name=setTerritoryIdCounter)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=setTerritoryIdCounter))]
     pub fn set_territory_id_counter(&mut self, count: u32) {
-        self.territory_id_counter = count;
+        self.history.begin_group();
+        self.set_id_counter(count);
+        self.history.end_group();
     }
 
-    /// Return new territory id to js
-    #[wasm_bindgen(js_name=getNewTerritoryId)]
-    pub fn get_new_territory_id(&mut self) -> u32 {
+    /// Return new territory id to js, error if ids are exhausted
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getNewTerritoryId))]
+    pub fn get_new_territory_id(&mut self) -> Result<u32, WorldError> {
         let new_id = self.territory_id_counter;
-        self.territory_id_counter += 1;
-        new_id
+        self.history.begin_group();
+        let result = self.reserve_id(new_id);
+        self.history.end_group();
+        result.map(|_| new_id)
     }
 
     /// Create new territory. Return id of new territory.
-    /// If input id is None, use a new id.
-    #[wasm_bindgen(js_name=createTerritory)]
-    pub fn create_territory(&mut self, id: Option<u32>) -> u32 {
-        print1("CREATING NEW TERR");
-        let id = if let Some(i) = id {
-            i
-        } else {
-            let new_id = self.territory_id_counter;
-            self.territory_id_counter += 1;
-            new_id
-        };
-        let territory = Territory::new(id);
-        self.territories.insert(id, territory);
+    /// If input id is None, use a new id, otherwise id counter is raised
+    /// above input id. Error if territory with input id already exists
+    /// or ids are exhausted.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=createTerritory))]
+    pub fn create_territory(&mut self, id: Option<u32>) -> Result<u32, WorldError> {
+        log_debug!("CREATING NEW TERR");
+        let id = id.unwrap_or(self.territory_id_counter);
+        if self.territories.contains_key(&id) {
+            return Err(WorldError::TerritoryExists(id));
+        }
+        self.history.begin_group();
+        let result = self.reserve_id(id);
+        if result.is_ok() {
+            self.insert_empty_territory(id);
+        }
+        self.history.end_group();
 
-        id
+        result.map(|_| id)
     }
 
-    #[wasm_bindgen(js_name=deleteTerritory)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=deleteTerritory))]
     pub fn delete_territory(&mut self, id: u32) {
-        print1("DELETE TERR");
+        log_debug!("DELETE TERR");
         // remove territory and its chunks from world grid
-        if let Some(territory) = self.territories.get(&id) {
-            let chunks = &territory.coords;
-            for p in chunks.iter() {
-                self.grid_occupied_coords.remove(&p);
-                self.grid.remove(&p);
-            }
-
-            self.territories.remove(&id);
-        }
+        self.history.begin_group();
+        self.remove_territory(id);
+        self.history.end_group();
     }
 
-    #[wasm_bindgen(js_name=getTerritorySize)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritorySize))]
     pub fn get_territory_size(&self, id: u32) -> Option<u32> {
         if let Some(territory) = self.territories.get(&id) {
             return Some(territory.coords.len() as u32);
@@ -166,7 +618,158 @@ impl World {
         return None;
     }
 
-    #[wasm_bindgen(js_name=getTerritoryChunksBuffer)]
+    /// Territory owning chunk (x, y), None if unclaimed.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryAtChunk))]
+    pub fn territory_at_chunk(&self, x: i32, y: i32) -> Option<u32> {
+        self.grid.get(&Point::new(x, y)).cloned()
+    }
+
+    /// Territory owning chunk containing block (bx, bz), None if unclaimed.
+    /// Chunk c covers blocks c * grid_scale to (c + 1) * grid_scale - 1,
+    /// so negative blocks round down (block -1 is in chunk -1).
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryAtBlock))]
+    pub fn territory_at_block(&self, bx: i32, bz: i32) -> Option<u32> {
+        self.territory_at_chunk(bx.div_euclid(self.grid_scale), bz.div_euclid(self.grid_scale))
+    }
+
+    /// Batch `territory_at_chunk`, chunk coords buffer [x1, y1, x2, y2, ...]
+    /// -> owners buffer [owner1, owner2, ...] in `encode_owner` format:
+    /// territory id + 1, 0 = unclaimed. Ignores trailing odd value.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoriesAtChunks))]
+    pub fn territories_at_chunks(&self, coords: Vec<i32>) -> Vec<u32> {
+        coords.chunks_exact(2)
+            .map(|p| encode_owner(self.territory_at_chunk(p[0], p[1])))
+            .collect()
+    }
+
+    /// Batch `territory_at_block`, block coords buffer [x1, z1, x2, z2, ...]
+    /// -> owners buffer [owner1, owner2, ...] in `encode_owner` format:
+    /// territory id + 1, 0 = unclaimed. Ignores trailing odd value.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoriesAtBlocks))]
+    pub fn territories_at_blocks(&self, coords: Vec<i32>) -> Vec<u32> {
+        coords.chunks_exact(2)
+            .map(|p| encode_owner(self.territory_at_block(p[0], p[1])))
+            .collect()
+    }
+
+    /// Owners of chunks in rectangle [xmin, xmax] x [ymin, ymax] (inclusive),
+    /// row-major buffer with width = xmax - xmin + 1, chunk (x, y) at index
+    /// (y - ymin) * width + (x - xmin). Values are NOT territory ids, they
+    /// are territory id + 1 with 0 = unclaimed (`encode_owner`), subtract
+    /// 1 to get the id. Error if raster has more than 2^24 values.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getOwnershipRaster))]
+    pub fn ownership_raster(&self, xmin: i32, ymin: i32, xmax: i32, ymax: i32) -> Result<Vec<u32>, WorldError> {
+        self.ownership_raster_downsampled(xmin, ymin, xmax, ymax, 1)
+    }
+
+    /// Downsampled `ownership_raster` for zoomed out views, each value is
+    /// the majority owner of an n x n block of chunks starting at
+    /// (xmin, ymin) (blocks at max edges may be smaller). Unclaimed
+    /// chunks count as owner 0, ties go to the smaller value. Buffer
+    /// width = ceil((xmax - xmin + 1) / n), same value format as
+    /// `ownership_raster` (territory id + 1, 0 = unclaimed). Error if
+    /// n = 0 or downsampled raster has more than 2^24 values.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getOwnershipRasterDownsampled))]
+    pub fn ownership_raster_downsampled(&self, xmin: i32, ymin: i32, xmax: i32, ymax: i32, n: u32) -> Result<Vec<u32>, WorldError> {
+        if xmax < xmin || ymax < ymin {
+            return Err(WorldError::InvalidBounds);
+        }
+        if n == 0 {
+            return Err(WorldError::InvalidDownsample(n));
+        }
+
+        let owner = |x: i64, y: i64| encode_owner(self.grid.get(&Point::new(x as i32, y as i32)).cloned());
+
+        let n = n as i64;
+        let (xmin, ymin, xmax, ymax) = (xmin as i64, ymin as i64, xmax as i64, ymax as i64);
+        let width = (xmax - xmin + n) / n;
+        let height = (ymax - ymin + n) / n;
+
+        let size = (width as u64).saturating_mul(height as u64);
+        if size > MAX_RASTER_SIZE {
+            return Err(WorldError::RasterTooLarge(size, MAX_RASTER_SIZE));
+        }
+
+        let mut raster: Vec<u32> = Vec::with_capacity(size as usize);
+        if n == 1 {
+            for y in ymin..=ymax {
+                for x in xmin..=xmax {
+                    raster.push(owner(x, y));
+                }
+            }
+            return Ok(raster);
+        }
+
+        // count owners per block from claimed chunks only, so cost does
+        // not grow with block size n
+        let mut block_counts: FnvHashMap<(i64, i64), FnvHashMap<u32, u64>> = FnvHashMap::default();
+        for (p, id) in self.grid.iter() {
+            let (x, y) = (p.x as i64, p.y as i64);
+            if x < xmin || x > xmax || y < ymin || y > ymax {
+                continue;
+            }
+            let counts = block_counts.entry(((x - xmin) / n, (y - ymin) / n)).or_default();
+            *counts.entry(encode_owner(Some(*id))).or_insert(0) += 1;
+        }
+
+        for cy in 0..height {
+            for cx in 0..width {
+                let counts = match block_counts.get(&(cx, cy)) {
+                    Some(counts) => counts,
+                    None => {
+                        raster.push(0);
+                        continue;
+                    },
+                };
+                let x0 = xmin + cx * n;
+                let y0 = ymin + cy * n;
+                let block_size = (n.min(xmax + 1 - x0) * n.min(ymax + 1 - y0)) as u64;
+                let unclaimed = block_size - counts.values().sum::<u64>();
+
+                // unclaimed (0) is the smallest value, so it wins ties
+                let mut majority = (0, unclaimed);
+                for (value, count) in counts.iter() {
+                    if *count > majority.1 || (*count == majority.1 && *value < majority.0) {
+                        majority = (*value, *count);
+                    }
+                }
+                raster.push(majority.0);
+            }
+        }
+
+        Ok(raster)
+    }
+
+    /// Shape statistics of territory (see `stats.rs`),
+    /// None if territory does not exist.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryStats))]
+    pub fn territory_stats(&self, id: u32) -> Option<TerritoryStats> {
+        let territory = self.territories.get(&id)?;
+        Some(territory_stats(territory, self.grid_scale))
+    }
+
+    /// Shape statistics of territories as json array sorted by id:
+    /// [{ "id": id, "chunkArea": n, "blockArea": n, "perimeter": n,
+    ///    "components": n, "holes": n, "aabb": [xmin, ymin, xmax, ymax],
+    ///    "centroid": [x, y], "compactness": f, "inaccessibilityRadius": f or null }, ...]
+    /// All territories if ids not given, missing ids are skipped.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryStatsJson))]
+    pub fn territory_stats_batch(&self, ids: Option<Vec<u32>>) -> String {
+        let mut ids: Vec<u32> = match ids {
+            Some(ids) => ids,
+            None => self.territories.keys().cloned().collect(),
+        };
+        ids.sort();
+        ids.dedup();
+
+        let stats: Vec<Value> = ids.into_iter()
+            .filter_map(|id| self.territory_stats(id))
+            .map(|stats| stats.to_json())
+            .collect();
+        Value::Array(stats).to_string()
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryChunksBuffer))]
     pub fn get_territory_chunks_buffer(&self, id: u32) -> Vec<i32> {
         if let Some(territory) = self.territories.get(&id) {
             return territory.to_buffer();
@@ -174,7 +777,7 @@ impl World {
         return Vec::new();
     }
 
-    #[wasm_bindgen(js_name=getTerritoryBorder)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryBorder))]
     pub fn get_territory_border(&self, id: u32) -> Vec<i32> {
         if let Some(territory) = self.territories.get(&id) {
             return territory.get_border(self.grid_scale);
@@ -182,18 +785,22 @@ impl World {
         return Vec::new();
     }
 
-    #[wasm_bindgen(js_name=listTerritories)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=listTerritories))]
     pub fn list_territories(&self) {
         for (id, terr) in self.territories.iter() {
-            print1(&format!("[{}] terr = {:?}", id, &terr.coords));
+            log_debug!("[{}] terr = {:?}", id, &terr.coords);
         }
     }
 
-    #[wasm_bindgen(js_name=addCoordsToTerritory)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=addCoordsToTerritory))]
     pub fn add_coords_to_territory(&mut self, id: u32, coords: Vec<i32>) -> bool {
-        print1(&format!("[{}] coords = {:?}", id, coords));
+        log_debug!("[{}] coords = {:?}", id, coords);
+
+        if !self.territories.contains_key(&id) {
+            return false;
+        }
 
-        let mut new_coords: FnvHashSet<Point<i32>> = FnvHashSet::default();
+        self.history.begin_group();
         for i in (0..coords.len()).step_by(2) {
             let x = coords[i];
             let y = coords[i+1];
@@ -201,42 +808,105 @@ impl World {
 
             // add coords that are not occupied in world grid
             if !self.grid_occupied_coords.contains(&p) {
-                new_coords.insert(p);
-                self.grid_occupied_coords.insert(p);
-                self.grid.insert(p, id);
+                self.set_chunk_owner(p, Some(id));
             }
         }
-            
-        // insert coords into territory
-        if let Some(territory) = self.territories.get_mut(&id) {
-            territory.insert_coords(new_coords.into_iter());
-            return true;
-        }
+        self.history.end_group();
 
-        return false;
+        return true;
     }
 
     /// Remove coords in world grid from any associated territory
-    #[wasm_bindgen(js_name=removeCoords)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=removeCoords))]
     pub fn remove_coords(&mut self, coords: Vec<i32>) {
+        self.history.begin_group();
         for i in (0..coords.len()).step_by(2) {
             let x = coords[i];
             let y = coords[i+1];
             let p = Point::new(x, y);
 
-            // add coords that are not occupied in world grid
+            // remove coords that are occupied in world grid
             if self.grid_occupied_coords.contains(&p) {
-                self.grid_occupied_coords.remove(&p);
-                if let Some(terr_id) = self.grid.remove(&p) {
-                    if let Some(terr) = self.territories.get_mut(&terr_id) {
-                        terr.coords.remove(&p);
-                    }
+                self.set_chunk_owner(p, None);
+            }
+        }
+        self.history.end_group();
+    }
+
+    /// Claim the 4-connected unclaimed region containing chunk (x, y)
+    /// for territory id, region is bounded by other territories.
+    /// `bounds` = [xmin, ymin, xmax, ymax] chunk limit (inclusive),
+    /// defaults to world claimed chunks bounds. `max_chunks` optionally
+    /// limits region size. Errors without changing world if region
+    /// leaks outside bounds or exceeds max chunks.
+    /// Returns number of chunks claimed.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=floodFillTerritory))]
+    pub fn flood_fill_territory(
+        &mut self,
+        id: u32,
+        x: i32,
+        y: i32,
+        max_chunks: Option<u32>,
+        bounds: Option<Vec<i32>>,
+    ) -> Result<u32, WorldError> {
+        if !self.territories.contains_key(&id) {
+            return Err(WorldError::TerritoryNotFound(id));
+        }
+
+        let start = Point::new(x, y);
+        if self.grid_occupied_coords.contains(&start) {
+            return Err(WorldError::ChunkClaimed(x, y));
+        }
+
+        let bounds = match bounds {
+            Some(b) => {
+                if b.len() != 4 || b[0] > b[2] || b[1] > b[3] {
+                    return Err(WorldError::InvalidBounds);
+                }
+                AABB::new(Point::new(b[0], b[1]), Point::new(b[2], b[3]))
+            },
+            // no claimed chunks: region is always unbounded
+            None if self.grid.is_empty() => return Err(WorldError::FloodFillUnbounded),
+            None => AABB::from_points(self.grid.keys().cloned()),
+        };
+
+        // region must be enclosed by territories inside bounds,
+        // reaching a chunk outside bounds means region leaks
+        let mut region: Vec<Point<i32>> = Vec::new();
+        let mut visited: FnvHashSet<Point<i32>> = FnvHashSet::default();
+        let mut stack: Vec<Point<i32>> = vec![start];
+        visited.insert(start);
+
+        while let Some(p) = stack.pop() {
+            if !bounds.contains(&p) {
+                return Err(WorldError::FloodFillUnbounded);
+            }
+            region.push(p);
+            if let Some(max_chunks) = max_chunks {
+                if region.len() > max_chunks as usize {
+                    return Err(WorldError::FloodFillTooLarge(max_chunks));
+                }
+            }
+            for (dx, dy) in NEIGHBORS_4.iter() {
+                // skip neighbors past i32 coord limits
+                let q = match (p.x.checked_add(*dx), p.y.checked_add(*dy)) {
+                    (Some(qx), Some(qy)) => Point::new(qx, qy),
+                    _ => continue,
+                };
+                if !self.grid_occupied_coords.contains(&q) && visited.insert(q) {
+                    stack.push(q);
                 }
             }
         }
+
+        self.history.begin_group();
+        self.add_points_to_territory(id, region.iter().cloned());
+        self.history.end_group();
+
+        Ok(region.len() as u32)
     }
 
-    #[wasm_bindgen(js_name=addCircleToTerritory)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=addCircleToTerritory))]
     pub fn add_circle_to_territory(&mut self, id: u32, cx: i32, cy: i32, radius: i32) -> bool {
         // ignore 0 or negative radius
         if radius <= 0 {
@@ -244,7 +914,7 @@ impl World {
         }
         
         // add radius to territory if it exists
-        if let Some(territory) = self.territories.get_mut(&id) {
+        if self.territories.contains_key(&id) {
             // chunks in circle radius
             let mut new_chunks: FnvHashSet<Point<i32>> = FnvHashSet::default();
 
@@ -264,11 +934,11 @@ impl World {
 
             if unoccupied.len() > 0 {
                 // mark chunks in global grid
-                for chunk in unoccupied.iter() {
-                    self.grid.insert(*chunk, id);
-                    self.grid_occupied_coords.insert(*chunk);
+                self.history.begin_group();
+                for chunk in unoccupied.into_iter() {
+                    self.set_chunk_owner(chunk, Some(id));
                 }
-                territory.insert_coords(unoccupied.into_iter());
+                self.history.end_group();
 
                 return true;
             }
@@ -277,15 +947,15 @@ impl World {
         return false;
     }
 
-    #[wasm_bindgen(js_name=removeCircleToTerritory)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=removeCircleToTerritory))]
     pub fn remove_circle_to_territory(&mut self, id: u32, cx: i32, cy: i32, radius: i32) -> bool {
         // ignore 0 or negative radius
         if radius <= 0 {
             return false;
         }
         
-        // add radius to territory if it exists
-        if let Some(territory) = self.territories.get_mut(&id) {
+        // remove radius from territory if it exists
+        if self.territories.contains_key(&id) {
             // chunks in circle radius
             let mut circle_chunks: FnvHashSet<Point<i32>> = FnvHashSet::default();
 
@@ -306,11 +976,11 @@ impl World {
 
             if circle_chunks.len() > 0 {
                 // remove chunks in global grid
-                for chunk in circle_chunks.iter() {
-                    self.grid.remove(chunk);
-                    self.grid_occupied_coords.remove(chunk);
+                self.history.begin_group();
+                for chunk in circle_chunks.into_iter() {
+                    self.set_chunk_owner(chunk, None);
                 }
-                territory.remove_coords(&circle_chunks);
+                self.history.end_group();
 
                 return true;
             }
@@ -319,99 +989,464 @@ impl World {
         return false;
     }
 
-    // calculate neighboring territories
-    // and checks if territory is an "edge" territory
-    // (i.e. has coords that do not border another territory)
-    #[wasm_bindgen(js_name=calculateNeighbors)]
-    pub fn calculate_neighbors(&mut self) {
-        for (id, terr) in self.territories.iter_mut() {
-            let neighbor_points = terr.get_neighboring_points();
+    /// Add chunks with centers inside polygon to territory, polygon
+    /// vertices are block coords [x1, y1, x2, y2, ...]. Does not take
+    /// chunks occupied by other territories. Error if coords are not
+    /// finite or shape bounding box exceeds MAX_SHAPE_SCAN_SIZE chunks
+    /// (same for other shape functions below).
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=addPolygonToTerritory))]
+    pub fn add_polygon_to_territory(&mut self, id: u32, vertices: Vec<f64>) -> Result<bool, WorldError> {
+        let chunks = rasterize_polygon(&points_from_buffer(&vertices), self.grid_scale)?;
+        Ok(self.add_unoccupied_chunks(id, chunks))
+    }
 
-            // calculate territories from neighbor points, and chec
-            let mut is_at_edge: bool = false;
-            let mut neighbor_territories: FnvHashSet<u32> = FnvHashSet::default();
-            for p in neighbor_points.iter() {
-                if let Some(id) = self.grid.get(&p) {
-                    neighbor_territories.insert(*id);
-                }
+    /// Remove territory chunks with centers inside polygon,
+    /// polygon vertices are block coords [x1, y1, x2, y2, ...].
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=removePolygonFromTerritory))]
+    pub fn remove_polygon_from_territory(&mut self, id: u32, vertices: Vec<f64>) -> Result<bool, WorldError> {
+        let chunks = rasterize_polygon(&points_from_buffer(&vertices), self.grid_scale)?;
+        Ok(self.remove_territory_chunks(id, chunks))
+    }
 
-                if !self.grid_occupied_coords.contains(&p) {
-                    is_at_edge = true;
-                }
-            }
+    /// Add chunks with centers inside block coords rectangle
+    /// [xmin, xmax] x [ymin, ymax] to territory.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=addRectToTerritory))]
+    pub fn add_rect_to_territory(&mut self, id: u32, xmin: f64, ymin: f64, xmax: f64, ymax: f64) -> Result<bool, WorldError> {
+        let chunks = rasterize_rect(xmin, ymin, xmax, ymax, self.grid_scale)?;
+        Ok(self.add_unoccupied_chunks(id, chunks))
+    }
 
-            terr.neighbors = neighbor_territories;
-            terr.is_at_edge = is_at_edge;
-        }
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=removeRectFromTerritory))]
+    pub fn remove_rect_from_territory(&mut self, id: u32, xmin: f64, ymin: f64, xmax: f64, ymax: f64) -> Result<bool, WorldError> {
+        let chunks = rasterize_rect(xmin, ymin, xmax, ymax, self.grid_scale)?;
+        Ok(self.remove_territory_chunks(id, chunks))
     }
-    
-    // return neighbors to territory as vector of territory ids
-    #[wasm_bindgen(js_name=getTerritoryNeighbors)]
-    pub fn get_territory_neighbors(&self, id: u32) -> Vec<i32> {
-        if let Some(territory) = self.territories.get(&id) {
-            return Vec::from_iter(territory.neighbors.iter().map(|&v| v as i32))
+
+    /// Add chunks with centers within width / 2 blocks of polyline
+    /// path, block coords [x1, y1, x2, y2, ...], to territory.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=addPolylineToTerritory))]
+    pub fn add_polyline_to_territory(&mut self, id: u32, path: Vec<f64>, width: f64) -> Result<bool, WorldError> {
+        let chunks = rasterize_polyline(&points_from_buffer(&path), width, self.grid_scale)?;
+        Ok(self.add_unoccupied_chunks(id, chunks))
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=removePolylineFromTerritory))]
+    pub fn remove_polyline_from_territory(&mut self, id: u32, path: Vec<f64>, width: f64) -> Result<bool, WorldError> {
+        let chunks = rasterize_polyline(&points_from_buffer(&path), width, self.grid_scale)?;
+        Ok(self.remove_territory_chunks(id, chunks))
+    }
+
+    /// Absorb unclaimed regions enclosed only by territory id (holes
+    /// inside territory) into the territory. Only holes with at most
+    /// `max_hole_size` chunks are filled, if given.
+    /// Returns number of chunks filled.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=fillHoles))]
+    pub fn fill_holes(&mut self, id: u32, max_hole_size: Option<u32>) -> u32 {
+        // holes enclosed by territory are inside its bounding box
+        let aabb = match self.index.territory_aabb(id) {
+            Some(aabb) => aabb,
+            None => return 0,
+        };
+
+        let holes: Vec<Vec<Point<i32>>> = find_holes(&self.grid, &aabb).into_iter()
+            .filter(|hole| hole.borders.len() == 1 && hole.borders[0].0 == id)
+            .filter(|hole| max_hole_size.map_or(true, |max| hole.chunks.len() <= max as usize))
+            .map(|hole| hole.chunks)
+            .collect();
+
+        let mut filled: u32 = 0;
+        self.history.begin_group();
+        for hole in holes.into_iter() {
+            filled += hole.len() as u32;
+            self.add_points_to_territory(id, hole);
         }
-        return Vec::new();
+        self.history.end_group();
+
+        return filled;
     }
 
-    // apply graph coloring on territories to generate colors
-    // assume planar graph with no loops, no double edges
-    #[wasm_bindgen(js_name=generateColors)]
-    pub fn generate_colors(&mut self) {
-        // reset all colors to none
-        for (_, mut terr) in self.territories.iter_mut() {
-            terr.color = None;
+    /// Fill holes enclosed by a single territory for all territories.
+    /// If `assign_shared` is true, holes enclosed by multiple territories
+    /// are assigned to the territory sharing the longest border with
+    /// the hole. Returns number of chunks filled.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=fillAllHoles))]
+    pub fn fill_all_holes(&mut self, max_hole_size: Option<u32>, assign_shared: Option<bool>) -> u32 {
+        if self.grid.is_empty() {
+            return 0;
         }
+        let assign_shared = assign_shared.unwrap_or(false);
 
-        let colors = graph_6_coloring(&self.territories);
-        
-        // write colors to territories
-        for (id, color) in colors.iter() {
-            if let Some(terr) = self.territories.get_mut(id) {
-                terr.color = Some(*color);
+        let aabb = AABB::from_points(self.grid.keys().cloned());
+        let holes = find_holes(&self.grid, &aabb);
+
+        let mut filled: u32 = 0;
+        self.history.begin_group();
+        for hole in holes.into_iter() {
+            if max_hole_size.is_some_and(|max| hole.chunks.len() > max as usize) {
+                continue;
+            }
+            if hole.borders.len() > 1 && !assign_shared {
+                continue;
+            }
+
+            // longest border, ties broken by smaller id (borders sorted by id)
+            let owner = hole.borders.iter()
+                .fold(None, |best: Option<(u32, u32)>, &(id, length)| match best {
+                    Some((_, best_length)) if best_length >= length => best,
+                    _ => Some((id, length)),
+                });
+
+            if let Some((id, _)) = owner {
+                filled += hole.chunks.len() as u32;
+                self.add_points_to_territory(id, hole.chunks);
             }
         }
+        self.history.end_group();
+
+        return filled;
     }
 
-    #[wasm_bindgen(js_name=getTerritoryColor)]
-    pub fn get_territory_color(&self, id: u32) -> Option<u8> {
-        if let Some(territory) = self.territories.get(&id) {
-            return territory.color.clone();
+    /// Grow territory by n chunk rings (4-neighborhood). Only grows into
+    /// unclaimed chunks, unless `steal` is true, then also takes chunks
+    /// from other territories. Returns true if any chunk added.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=dilateTerritory))]
+    pub fn dilate_territory(&mut self, id: u32, n: u32, steal: Option<bool>) -> bool {
+        let steal = steal.unwrap_or(false);
+        let mut changed = false;
+
+        self.history.begin_group();
+        for _ in 0..n {
+            let ring: Vec<Point<i32>> = match self.territories.get(&id) {
+                Some(terr) => terr.get_neighboring_points().into_iter()
+                    .filter(|p| steal || !self.grid_occupied_coords.contains(p))
+                    .collect(),
+                None => break,
+            };
+            if ring.is_empty() {
+                break;
+            }
+            for p in ring.into_iter() {
+                self.set_chunk_owner(p, Some(id));
+            }
+            changed = true;
         }
-        return None;
+        self.history.end_group();
+
+        return changed;
     }
 
-    #[wasm_bindgen(js_name=getTerritoryIsEdge)]
-    pub fn get_territory_is_edge(&self, id: u32) -> Option<bool> {
-        if let Some(territory) = self.territories.get(&id) {
-            return Some(territory.is_at_edge);
+    /// Shrink territory by n chunk rings, each ring removes chunks
+    /// with a 4-neighbor outside the territory.
+    /// Returns true if any chunk removed.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=erodeTerritory))]
+    pub fn erode_territory(&mut self, id: u32, n: u32) -> bool {
+        let mut changed = false;
+
+        self.history.begin_group();
+        for _ in 0..n {
+            let ring: Vec<Point<i32>> = match self.territories.get(&id) {
+                Some(terr) => terr.coords.iter()
+                    .filter(|p| NEIGHBORS_4.iter().any(|(dx, dy)| !terr.coords.contains(&Point::new(p.x + dx, p.y + dy))))
+                    .cloned()
+                    .collect(),
+                None => break,
+            };
+            if ring.is_empty() {
+                break;
+            }
+            for p in ring.into_iter() {
+                self.set_chunk_owner(p, None);
+            }
+            changed = true;
         }
-        return None;
+        self.history.end_group();
+
+        return changed;
     }
 
-    /// Doing in dumb way, just checking all coords in each territory
-    #[wasm_bindgen(js_name=getTerritoriesInAABB)]
-    pub fn get_territories_in_aabb(&self, xmin: i32, ymin: i32, xmax: i32, ymax: i32) -> Vec<u32> {
-        let mut terr_ids: Vec<u32> = Vec::new();
+    /// Smooth territory border, each iteration does:
+    /// 1. opening: remove one-chunk spikes (chunks with exactly one
+    ///    4-neighbor in territory)
+    /// 2. closing: fill one-chunk notches (unclaimed chunks with 3 or
+    ///    more 4-neighbors in territory)
+    ///
+    /// Unlike erode + dilate, this keeps territory corners.
+    /// Returns true if any chunk changed.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=smoothTerritory))]
+    pub fn smooth_territory(&mut self, id: u32, iterations: u32) -> bool {
+        let mut changed = false;
+
+        let count_neighbors = |coords: &FnvHashSet<Point<i32>>, p: &Point<i32>| {
+            NEIGHBORS_4.iter().filter(|(dx, dy)| coords.contains(&Point::new(p.x + dx, p.y + dy))).count()
+        };
 
-        'terr: for (id, terr) in self.territories.iter() {
-            for p in terr.coords.iter() {
-                if p.x < xmin || p.x > xmax || p.y < ymin || p.y > ymax {
-                    continue;
-                }
-                // inside
-                terr_ids.push(*id);
-                continue 'terr;
+        self.history.begin_group();
+        for _ in 0..iterations {
+            let (spikes, notches) = match self.territories.get(&id) {
+                Some(terr) => {
+                    let mut spikes: Vec<Point<i32>> = terr.coords.iter()
+                        .filter(|p| count_neighbors(&terr.coords, p) == 1)
+                        .cloned()
+                        .collect();
+                    // do not remove all chunks of a line territory
+                    if spikes.len() == terr.coords.len() {
+                        spikes.clear();
+                    }
+
+                    let notches: Vec<Point<i32>> = terr.get_neighboring_points().into_iter()
+                        .filter(|p| !self.grid_occupied_coords.contains(p) && count_neighbors(&terr.coords, p) >= 3)
+                        .collect();
+
+                    (spikes, notches)
+                },
+                None => break,
+            };
+            if spikes.is_empty() && notches.is_empty() {
+                break;
             }
+            for p in spikes.into_iter() {
+                self.set_chunk_owner(p, None);
+            }
+            for p in notches.into_iter() {
+                self.set_chunk_owner(p, Some(id));
+            }
+            changed = true;
         }
+        self.history.end_group();
 
-        return terr_ids;
+        return changed;
     }
 
-    /// Merge list of territories into single territory
-    /// Return id of the merged territory. This will be the id
+    // calculate neighboring territories
+    // and checks if territory is an "edge" territory
+    // (i.e. has coords that do not border another territory)
+    // full rebuild from territory coords, also rebuilds incremental
+    // adjacency counts used by `updateNeighbors`
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=calculateNeighbors))]
+    pub fn calculate_neighbors(&mut self) {
+        for (_, terr) in self.territories.iter_mut() {
+            let (neighbor_territories, is_at_edge) = calculate_territory_neighbors(terr, &self.grid, &self.grid_occupied_coords);
+            terr.neighbors = neighbor_territories;
+            terr.is_at_edge = is_at_edge;
+        }
+
+        self.adjacency.rebuild(&self.grid);
+        self.adjacency.take_dirty();
+    }
+
+    // update neighbors and edge flag only for territories touching
+    // chunks edited since last update, from incremental adjacency counts
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=updateNeighbors))]
+    pub fn update_neighbors(&mut self) {
+        for id in self.adjacency.take_dirty().into_iter() {
+            if let Some(terr) = self.territories.get_mut(&id) {
+                terr.neighbors = self.adjacency.neighbors(id);
+                terr.is_at_edge = self.adjacency.is_at_edge(id);
+            }
+        }
+    }
+
+    // verify incremental adjacency against full neighbor calculation,
+    // returns ids of territories where they differ (should be empty)
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=verifyNeighbors))]
+    pub fn verify_neighbors(&self) -> Vec<u32> {
+        let mut mismatched: Vec<u32> = Vec::new();
+        for (id, terr) in self.territories.iter() {
+            let (neighbor_territories, is_at_edge) = calculate_territory_neighbors(terr, &self.grid, &self.grid_occupied_coords);
+            if neighbor_territories != self.adjacency.neighbors(*id) || is_at_edge != self.adjacency.is_at_edge(*id) {
+                mismatched.push(*id);
+            }
+        }
+        mismatched.sort();
+        mismatched
+    }
+    
+    // return neighbors to territory as vector of territory ids
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryNeighbors))]
+    pub fn get_territory_neighbors(&self, id: u32) -> Vec<i32> {
+        if let Some(territory) = self.territories.get(&id) {
+            return Vec::from_iter(territory.neighbors.iter().map(|&v| v as i32))
+        }
+        return Vec::new();
+    }
+
+    /// Export territory adjacency graph as json, edge weights are
+    /// shared border lengths in chunk edges:
+    /// { "nodes": [{ "id", "size" }, ...], "edges": [{ "source", "target", "weight" }, ...] }
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getAdjacencyGraphJson))]
+    pub fn get_adjacency_graph_json(&self) -> String {
+        graph_to_json(&self.adjacency, &self.territory_sizes()).to_string()
+    }
+
+    /// Export territory adjacency graph in Graphviz DOT format.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getAdjacencyGraphDot))]
+    pub fn get_adjacency_graph_dot(&self) -> String {
+        graph_to_dot(&self.adjacency, &self.territory_sizes())
+    }
+
+    /// Return shortest hop path of territory ids [from, ..., to],
+    /// empty if territories are not connected.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryPath))]
+    pub fn get_territory_path(&self, from: u32, to: u32) -> Vec<u32> {
+        if !self.territories.contains_key(&from) || !self.territories.contains_key(&to) {
+            return Vec::new();
+        }
+        shortest_path(&self.adjacency, from, to).unwrap_or_default()
+    }
+
+    /// Return ids of territories within 1..=k hops of territory id
+    /// (excluding id), sorted.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoriesWithinHops))]
+    pub fn get_territories_within_hops(&self, id: u32, k: u32) -> Vec<u32> {
+        if !self.territories.contains_key(&id) {
+            return Vec::new();
+        }
+        within_hops(&self.adjacency, id, k)
+    }
+
+    /// Return groups of connected territories in subset of ids
+    /// (e.g. a town's claims), largest group first.
+    /// Output buffer format: [n1, id(1,1), id(1,2), ..., n2, id(2,1), ...]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getConnectedTerritoryGroups))]
+    pub fn get_connected_territory_groups(&self, ids: Vec<u32>) -> Vec<u32> {
+        let ids: FnvHashSet<u32> = ids.into_iter().filter(|id| self.territories.contains_key(id)).collect();
+        let mut buffer: Vec<u32> = Vec::with_capacity(2 * ids.len());
+        for component in subset_components(&self.adjacency, &ids).into_iter() {
+            buffer.push(component.len() as u32);
+            buffer.extend(component);
+        }
+        buffer
+    }
+
+    /// Chunk distances (4-neighborhood steps) from chunks of source
+    /// territories, up to max_distance. If `claimed_only` (default true)
+    /// distances are measured through claimed chunks only, otherwise
+    /// through all chunks, then max_distance is limited to 256 (error
+    /// above). See `DistanceField` for output buffers.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=distanceField))]
+    pub fn distance_field(&self, source_ids: Vec<u32>, max_distance: u32, claimed_only: Option<bool>) -> Result<DistanceField, WorldError> {
+        let claimed_only = claimed_only.unwrap_or(true);
+        if !claimed_only && max_distance > MAX_UNCLAIMED_DISTANCE {
+            return Err(WorldError::DistanceTooLarge(max_distance, MAX_UNCLAIMED_DISTANCE));
+        }
+        Ok(distance_field(&self.grid, &self.territories, &source_ids, max_distance, claimed_only))
+    }
+
+    // apply graph coloring on territories to generate colors
+    // assume planar graph with no loops, no double edges.
+    // default algorithm is planar 5-coloring (colors 0..4)
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=generateColors))]
+    pub fn generate_colors(&mut self, algorithm: Option<ColoringAlgorithm>) {
+        // reset all colors to none
+        for (_, terr) in self.territories.iter_mut() {
+            terr.color = None;
+        }
+
+        let colors = match algorithm.unwrap_or(ColoringAlgorithm::Planar5) {
+            ColoringAlgorithm::Greedy6 => graph_6_coloring(&self.territories),
+            ColoringAlgorithm::Planar5 => graph_5_coloring(&self.territories),
+        };
+        
+        // write colors to territories
+        for (id, color) in colors.iter() {
+            if let Some(terr) = self.territories.get_mut(id) {
+                terr.color = Some(*color);
+            }
+        }
+    }
+
+    /// Recolor territories while keeping existing colors stable:
+    /// only territories with missing or invalid colors, or colors
+    /// conflicting with a neighbor, are recolored. Neighbors must be
+    /// up to date (`updateNeighbors`). Returns ids of territories whose
+    /// color changed, sorted by id.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=generateColorsStable))]
+    pub fn generate_colors_stable(&mut self) -> Vec<u32> {
+        let colors = graph_color_stable(&self.territories);
+
+        let mut changed: Vec<u32> = Vec::new();
+        for (id, terr) in self.territories.iter_mut() {
+            let color = colors.get(id).cloned();
+            if terr.color != color {
+                terr.color = color;
+                changed.push(*id);
+            }
+        }
+        changed.sort();
+
+        return changed;
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=setTerritoryColor))]
+    pub fn set_territory_color(&mut self, id: u32, color: Option<u8>) {
+        if let Some(territory) = self.territories.get_mut(&id) {
+            territory.color = color;
+        }
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryColor))]
+    #[allow(clippy::clone_on_copy)]
+    pub fn get_territory_color(&self, id: u32) -> Option<u8> {
+        if let Some(territory) = self.territories.get(&id) {
+            return territory.color.clone();
+        }
+        return None;
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryIsEdge))]
+    pub fn get_territory_is_edge(&self, id: u32) -> Option<bool> {
+        if let Some(territory) = self.territories.get(&id) {
+            return Some(territory.is_at_edge);
+        }
+        return None;
+    }
+
+    /// Validate world before saving, returns json array of issues:
+    /// [{ "kind": name, "territories": [id, ...], "chunks": [x1, y1, ...] }, ...]
+    /// See `validate.rs` for issue kinds. Uses default options
+    /// (all checks, no min size) if options not given.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=validate))]
+    pub fn validate(&self, options: Option<ValidationOptions>) -> String {
+        let options = options.unwrap_or_default();
+        let issues = validate_world(&self.grid, &self.grid_occupied_coords, &self.territories, &options);
+        let issues: Vec<Value> = issues.iter().map(|issue| issue.to_json()).collect();
+        Value::Array(issues).to_string()
+    }
+
+    /// Return ids of territories with chunks inside chunk AABB
+    /// [xmin, xmax] x [ymin, ymax] (inclusive), sorted by id.
+    /// Uses spatial index, only visits tiles overlapping the AABB.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoriesInAABB))]
+    pub fn get_territories_in_aabb(&self, xmin: i32, ymin: i32, xmax: i32, ymax: i32) -> Vec<u32> {
+        if xmin > xmax || ymin > ymax {
+            return Vec::new();
+        }
+        let aabb = AABB::new(Point::new(xmin, ymin), Point::new(xmax, ymax));
+        self.index.query_aabb(&aabb, &self.grid)
+    }
+
+    /// Return ids of territories with chunks at distance < radius from
+    /// chunk (cx, cy), same chunks as circle brushes, sorted by id.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoriesInRadius))]
+    pub fn get_territories_in_radius(&self, cx: i32, cy: i32, radius: f64) -> Vec<u32> {
+        self.index.query_radius(cx, cy, radius, &self.grid)
+    }
+
+    /// Return territory chunk bounding box as [xmin, ymin, xmax, ymax],
+    /// or empty buffer if territory has no chunks.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getTerritoryAABB))]
+    pub fn get_territory_aabb(&self, id: u32) -> Vec<i32> {
+        if let Some(aabb) = self.index.territory_aabb(id) {
+            return vec![aabb.min.x, aabb.min.y, aabb.max.x, aabb.max.y];
+        }
+        return Vec::new();
+    }
+
+    /// Merge list of territories into single territory
+    /// Return id of the merged territory. This will be the id
     /// of the first element in ids
-    #[wasm_bindgen(js_name=mergeTerritories)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=mergeTerritories))]
+    #[allow(clippy::needless_borrow)]
     pub fn merge_territories(&mut self, ids: Vec<u32>) -> Option<u32> {
         // trivial cases, no merge occur
         if ids.len() == 0 {
@@ -434,194 +1469,678 @@ impl World {
 
         // merge territories into first id
         let merged_id = ids[0];
-        let mut merged_terr = self.territories.remove(&merged_id)?;
-        // remove old territory, insert chunks into merged territory id
+        self.history.begin_group();
+        // move chunks into merged territory id, remove old territory
         for id in ids[1..].iter() {
-            if let Some(territory) = self.territories.remove(&id) {
-                for p in territory.coords.into_iter() {
-                    merged_terr.coords.insert(p);
-                    self.grid.insert(p, merged_id);
-                }
+            if *id == merged_id {
+                continue;
+            }
+            if let Some(territory) = self.remove_territory(*id) {
+                self.add_points_to_territory(merged_id, territory.coords);
             }
         }
-
-        // reinsert merged territory
-        self.territories.insert(merged_id, merged_terr);
+        self.history.end_group();
 
         return Some(merged_id);
     }
 
-    /// Subdivide an existing territory into randomly generated territories
-    #[wasm_bindgen(js_name=subdivideIntoRandomTerritories)]
-    pub fn subdivide_into_random_territories(
-        &mut self,
-        id: u32,
-        average_radius: f64,
-        scale_x: f64,
-        scale_y: f64,
-        random_seed: Option<u32>,
-        iterations_improve_center: u32,
-        iterations_improve_corner: u32,
-        delete_smaller_than: u32,
-        merge_smaller_than: u32,
-    ) -> Option<Vec<u32>> {
-        if let Some(territory) = self.remove_territory(id) {
+    /// Move boundary chunks from oversized territories to undersized
+    /// neighboring territories in ids (empty = all territories), until
+    /// all sizes are within target +/- tolerance or no move is possible.
+    /// Target defaults to average size of territories. A chunk is only
+    /// moved if donor territory stays 4-connected around the chunk.
+    /// Each round moves a whole boundary layer of each donor, so number
+    /// of rounds grows with distance chunks diffuse, not chunks moved.
+    /// Returns number of chunks moved.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=rebalanceSizes))]
+    pub fn rebalance_sizes(&mut self, ids: Vec<u32>, target: Option<u32>, tolerance: u32) -> u32 {
+        let mut ids: Vec<u32> = if ids.len() > 0 {
+            ids.into_iter().filter(|id| self.territories.contains_key(id)).collect()
+        } else {
+            self.territories.keys().cloned().collect()
+        };
+        ids.sort();
+        ids.dedup();
+
+        if ids.len() < 2 {
+            return 0;
+        }
 
-            // get min/max from territory bounding box, slightly expand it
-            let aabb = territory.get_aabb();
-            let min = ((aabb.min.x - 1) as f64, (aabb.min.y - 1) as f64);
-            let max = ((aabb.max.x + 1) as f64, (aabb.max.y + 1) as f64);
+        let target = match target {
+            Some(target) => target as i64,
+            None => {
+                let total: usize = ids.iter().map(|id| self.territories[id].coords.len()).sum();
+                (total as f64 / ids.len() as f64).round() as i64
+            },
+        };
+        let tolerance = tolerance as i64;
 
-            let mut random_cells: CellDiagram = generate_random_cells(
-                average_radius,
-                &min,
-                &max,
-                random_seed,
-                iterations_improve_center,
-                iterations_improve_corner,
-            );
+        let selected: FnvHashSet<u32> = ids.iter().cloned().collect();
+        let size = |world: &World, id: u32| world.territories[&id].coords.len() as i64;
 
-            if scale_x != 1.0 || scale_y != 1.0 {
-                // note: this does not properly update centroid positions
-                random_cells.scale((scale_x, scale_y));
-            }
-            random_cells.calculate_bounding_boxes();
+        // move from donor d to receiver r is legal if either is outside
+        // tolerance and donor is larger by at least 2 chunks, so sum of
+        // squared sizes always decreases (moves cannot cycle) and sizes
+        // can diffuse through territories already within tolerance
+        let is_legal = |d: i64, r: i64| {
+            d - r >= 2 && (d > target + tolerance || r < target - tolerance)
+        };
+
+        let mut moved: u32 = 0;
 
-            let mut new_territories: Vec<Vec<Point<i32>>> = (0..random_cells.num_cells())
-                .map(|_| Vec::new())
-                .collect();
+        self.history.begin_group();
+        loop {
+            // largest donors first
+            let mut donors: Vec<u32> = ids.to_vec();
+            donors.sort_by_key(|id| (-size(self, *id), *id));
 
-            // assign to new territory
-            for p in territory.coords.into_iter() {
-                if let Some(idx) = random_cells.cell_contains_coords(p.x as f64, p.y as f64) {
-                    new_territories[idx].push(p);
+            let mut moved_in_round = false;
+
+            for donor in donors.into_iter() {
+                let donor_size = size(self, donor);
+                if donor_size <= 1 {
+                    continue;
                 }
-            }
 
-            let mut new_territory_ids: Vec<u32> = Vec::new();
+                // boundary layer candidates ordered by smallest receiver,
+                // then most receiver neighbors (keeps borders compact),
+                // then smallest coord
+                let mut candidates: Vec<RebalanceCandidate> = Vec::new();
+                for p in self.territories[&donor].coords.iter() {
+                    let mut receivers: Vec<u32> = Vec::with_capacity(4);
+                    for (dx, dy) in NEIGHBORS_4.iter() {
+                        if let Some(owner) = self.grid.get(&Point::new(p.x + dx, p.y + dy)) {
+                            if *owner != donor && selected.contains(owner) {
+                                receivers.push(*owner);
+                            }
+                        }
+                    }
 
-            for terr in new_territories.into_iter() {
-                if terr.len() > 0 {
-                    // skip if delete created territories smaller than size
-                    if delete_smaller_than > 0 && terr.len() < delete_smaller_than as usize {
-                        continue;
+                    for r in receivers.iter() {
+                        let r_size = size(self, *r);
+                        if !is_legal(donor_size, r_size) {
+                            continue;
+                        }
+                        let shared = receivers.iter().filter(|x| *x == r).count() as i64;
+                        candidates.push(((r_size, -shared, p.x, p.y), *p, *r));
                     }
+                }
+                candidates.sort_by_key(|(key, _, _)| *key);
 
-                    let id = self.create_territory(None);
-                    self.add_points_to_territory(id, terr);
-                    new_territory_ids.push(id);
+                // sizes and donor shape change with each move, recheck
+                for (_, p, receiver) in candidates.into_iter() {
+                    if self.grid.get(&p) != Some(&donor) || !is_legal(size(self, donor), size(self, receiver)) {
+                        continue;
+                    }
+                    if !is_simple_point(&self.territories[&donor].coords, p) {
+                        continue;
+                    }
+                    self.set_chunk_owner(p, Some(receiver));
+                    moved += 1;
+                    moved_in_round = true;
                 }
             }
 
-            // run merging, if territory smaller than some value
-            // merge with SMALLEST neighbor
-            if merge_smaller_than > 0 {
-                // need to know territory neighbors
-                self.calculate_neighbors();
+            if !moved_in_round {
+                break;
+            }
+        }
+        self.history.end_group();
+
+        return moved;
+    }
 
-                let merge_size = merge_smaller_than as usize;
+    /// Merge territories smaller than threshold chunks into a neighbor,
+    /// chosen by strategy (default smallest neighbor). Adjacent small
+    /// territories can merge with each other, merged territories that
+    /// are still too small keep merging. If `ids` is given, only these
+    /// territories are merged and merge targets. Territories with no
+    /// neighbors are kept. Returns ids of removed territories, sorted.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=mergeSmallTerritories))]
+    pub fn merge_small_territories(&mut self, threshold: u32, strategy: Option<MergeStrategy>, ids: Option<Vec<u32>>) -> Vec<u32> {
+        let selection: Option<FnvHashSet<u32>> = ids.map(|ids| ids.into_iter().collect());
+
+        self.history.begin_group();
+        let removed = self.merge_small_territories_recorded(
+            threshold,
+            strategy.unwrap_or(MergeStrategy::SmallestNeighbor),
+            selection.as_ref(),
+        );
+        self.history.end_group();
+
+        removed
+    }
 
-                let terr_too_small: Vec<u32> = self.territories.iter()
-                    .filter(|(_, v)| v.coords.len() <= merge_size)
-                    .map(|(k, _)| k)
-                    .cloned()
-                    .collect();
+    /// Subdivide an existing territory into randomly generated territories
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=subdivideIntoRandomTerritories))]
+    pub fn subdivide_into_random_territories(&mut self, id: u32, options: &GeneratorOptions) -> Option<Vec<u32>> {
+        if !self.territories.contains_key(&id) {
+            return None;
+        }
 
-                let terr_too_small: Vec<Territory> = terr_too_small.into_iter()
-                    .map(|id| self.remove_territory(id).unwrap())
-                    .collect();
-                
-                let mut remaining_territories: Vec<Territory> = Vec::new();
-
-                for terr in terr_too_small.into_iter() {
-                    // join first neighbor by default
-                    let mut neighbor_to_join = None;
-                    let mut neighbor_to_join_size = usize::MAX;
-
-                    // find smallest neighbor thats larger than merge size to join with
-                    for id in terr.neighbors.iter() {
-                        if let Some(neighbor) = self.territories.get(id) {
-                            let neighbor_size = neighbor.coords.len();
-                            if neighbor_size < neighbor_to_join_size && neighbor.coords.len() > merge_size {
-                                neighbor_to_join = Some(*id);
-                                neighbor_to_join_size = neighbor_size;
-                            }
-                        }
-                    }
+        self.history.begin_group();
+        let new_territory_ids = self.subdivide_into_random_territories_recorded(id, options);
+        self.history.end_group();
 
-                    if let Some(neighbor_id) = neighbor_to_join {
-                        self.add_points_to_territory(neighbor_id, terr.coords);
-                    }
-                    else {
-                        remaining_territories.push(terr);
+        new_territory_ids
+    }
+
+    /// Generate new territories over unclaimed chunks in a region, same
+    /// random cell generation as `subdivideIntoRandomTerritories`.
+    /// Region is chunk `bounds` [xmin, ymin, xmax, ymax] (inclusive)
+    /// and/or chunk `mask` [x1, y1, x2, y2, ...], if both are given
+    /// region is mask chunks inside bounds. Claimed chunks in region
+    /// are left untouched. Bounds without mask are limited to 2^22
+    /// chunks (error above). Returns ids of new territories.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=generateTerritoriesInRegion))]
+    pub fn generate_territories_in_region(
+        &mut self,
+        bounds: Option<Vec<i32>>,
+        mask: Option<Vec<i32>>,
+        options: &GeneratorOptions,
+    ) -> Result<Vec<u32>, WorldError> {
+        let bounds = match bounds {
+            Some(b) => {
+                if b.len() != 4 || b[0] > b[2] || b[1] > b[3] {
+                    return Err(WorldError::InvalidBounds);
+                }
+                Some(AABB::new(Point::new(b[0], b[1]), Point::new(b[2], b[3])))
+            },
+            None => None,
+        };
+
+        let region: Vec<Point<i32>> = match (mask, bounds) {
+            (Some(mask), _) => {
+                let mask: FnvHashSet<Point<i32>> = mask.chunks_exact(2)
+                    .map(|p| Point::new(p[0], p[1]))
+                    .filter(|p| bounds.map_or(true, |b| b.contains(p)))
+                    .collect();
+                mask.into_iter().collect()
+            },
+            (None, Some(b)) => {
+                let width = (b.max.x as i64 - b.min.x as i64 + 1) as u64;
+                let height = (b.max.y as i64 - b.min.y as i64 + 1) as u64;
+                let size = width.saturating_mul(height);
+                if size > MAX_GENERATE_REGION_SIZE {
+                    return Err(WorldError::RegionTooLarge(size, MAX_GENERATE_REGION_SIZE));
+                }
+                let mut region = Vec::with_capacity(size as usize);
+                for x in b.min.x..=b.max.x {
+                    for y in b.min.y..=b.max.y {
+                        region.push(Point::new(x, y));
                     }
                 }
+                region
+            },
+            (None, None) => return Err(WorldError::InvalidBounds),
+        };
+
+        let unclaimed: Vec<Point<i32>> = region.into_iter()
+            .filter(|p| !self.grid_occupied_coords.contains(p))
+            .collect();
+
+        self.history.begin_group();
+        let new_territory_ids = self.generate_territories_from_coords_recorded(unclaimed, options);
+        self.history.end_group();
+
+        Ok(new_territory_ids)
+    }
+
+    /// Diff this world -> other world (see `diff.rs`).
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn diff(&self, other: &World) -> WorldDiff {
+        compute_diff(
+            &self.grid,
+            &self.territories,
+            &self.adjacency,
+            &other.grid,
+            &other.territories,
+            &other.adjacency,
+        )
+    }
+
+    /// Replay a diff on this world as one undo step: creates territories
+    /// (with their color and meta), applies chunk owner changes, then
+    /// deletes territories. Diff must apply cleanly (created ids are free,
+    /// deleted ids exist and all their chunks are moved by the diff, chunk
+    /// owners equal diff previous owners), otherwise nothing is changed.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=applyDiff))]
+    pub fn apply_diff(&mut self, diff: &WorldDiff) -> Result<(), WorldError> {
+        let created: FnvHashSet<u32> = diff.created_ids().into_iter().collect();
+        let deleted: FnvHashSet<u32> = diff.deleted().iter().cloned().collect();
+
+        for id in created.iter() {
+            if self.territories.contains_key(id) {
+                return Err(WorldError::DiffTerritoryExists(*id));
+            }
+            if *id == u32::MAX {
+                return Err(WorldError::TerritoryIdOutOfRange(*id));
+            }
+        }
+        for id in deleted.iter() {
+            if !self.territories.contains_key(id) {
+                return Err(WorldError::TerritoryNotFound(*id));
+            }
+        }
 
-                // territories with no merge candidates, just re-add to world
-                // TODO: merge with each other then re-add to world
-                // may be expensive, need to recalculate neighbors
-                for terr in remaining_territories.into_iter() {
-                    self.add_territory(terr);
+        // chunks leaving each deleted territory
+        let mut deleted_chunks_moved: FnvHashMap<u32, usize> = FnvHashMap::default();
+        for c in diff.chunks().iter() {
+            if self.grid.get(&c.p).cloned() != c.prev {
+                return Err(WorldError::DiffChunkConflict(c.p.x, c.p.y));
+            }
+            if let Some(next) = c.next {
+                if !self.territories.contains_key(&next) && !created.contains(&next) {
+                    return Err(WorldError::TerritoryNotFound(next));
+                }
+                if deleted.contains(&next) {
+                    return Err(WorldError::DiffTerritoryNotEmpty(next));
+                }
+            }
+            if let Some(prev) = c.prev {
+                if deleted.contains(&prev) && c.next != c.prev {
+                    *deleted_chunks_moved.entry(prev).or_default() += 1;
                 }
+            }
+        }
 
-                // remove invalid new territories
-                new_territory_ids.retain(|x| self.territories.contains_key(x));
+        // deleted territory must not keep chunks the diff does not list,
+        // e.g. chunks claimed after the diff was made
+        for id in deleted.iter() {
+            let moved = deleted_chunks_moved.get(id).cloned().unwrap_or(0);
+            if moved != self.territories[id].coords.len() {
+                return Err(WorldError::DiffTerritoryNotEmpty(*id));
             }
-            
-            return Some(new_territory_ids);
         }
 
-        return None;
+        self.history.begin_group();
+        for terr in diff.created().iter() {
+            self.insert_territory(terr.id, terr.color, terr.meta.clone());
+            // cannot fail, u32 max rejected above
+            let _ = self.reserve_id(terr.id);
+        }
+        for c in diff.chunks().iter() {
+            self.set_chunk_owner(c.p, c.next);
+        }
+        for id in diff.deleted().iter() {
+            self.remove_territory(*id);
+        }
+        self.history.end_group();
+
+        Ok(())
     }
-}
 
+    /// Three-way merge, this world is the common base of edited worlds
+    /// a and b and is changed into the merged world as one undo step.
+    /// Non-conflicting chunk ownership changes from both are applied,
+    /// new territories from b colliding with new ids from a get new
+    /// ids (with color and meta copied from their source world). See
+    /// `merge.rs` for conflict rules, conflicting chunks keep their
+    /// base owner and are reported in result for manual resolution.
+    /// Error (world unchanged) if territory ids for remapping run out.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn merge(&mut self, a: &World, b: &World) -> Result<MergeResult, WorldError> {
+        let diff_a = compute_diff(&self.grid, &self.territories, &self.adjacency, &a.grid, &a.territories, &a.adjacency);
+        let diff_b = compute_diff(&self.grid, &self.territories, &self.adjacency, &b.grid, &b.territories, &b.adjacency);
+        // new ids above every id counter and every id present in any
+        // world, counters may lag behind ids inserted directly
+        let base_ids: Vec<u32> = self.territories.keys().cloned().collect();
+        let next_id = base_ids.iter()
+            .chain(a.territories.keys())
+            .chain(b.territories.keys())
+            .map(|id| id.saturating_add(1))
+            .fold(self.territory_id_counter, u32::max)
+            .max(a.territory_id_counter)
+            .max(b.territory_id_counter);
+        let mut plan = merge_diffs(&diff_a, &diff_b, &base_ids, next_id)?;
+
+        self.history.begin_group();
+
+        let mut id_counter = next_id;
+        for (id, new_id, from_b) in plan.created.iter() {
+            let source = if *from_b { b } else { a };
+            match source.territories.get(id) {
+                Some(source_territory) => self.insert_territory(*new_id, source_territory.color, source_territory.meta.clone()),
+                None => self.insert_empty_territory(*new_id),
+            };
+            id_counter = id_counter.max(new_id.saturating_add(1));
+        }
+        self.set_id_counter(id_counter);
 
-// recursive backtracing loop for coloring nodes
-// incredibly slow and should not be used
-// returns
-// true - if successfully assigned unique color
-// false - if failed to assign unique color
-fn graph_color_recursive(territories: &mut FnvHashMap<u32, Territory>, node_id: u32) -> bool {
-
-    // get neighbor colors
-    let neighbors = territories.get(&node_id).unwrap().neighbors.clone();
-    let mut neighbor_colors: [bool; MAX_COLORS] = [false; MAX_COLORS];
-    for neighbor_id in neighbors.iter() {
-        if let Some(color) = territories.get(&neighbor_id).unwrap().color {
-            let color = color as usize;
-            if color < MAX_COLORS {
-                neighbor_colors[color] = true;
+        for (p, owner) in plan.chunks.iter() {
+            self.set_chunk_owner(*p, *owner);
+        }
+
+        for id in plan.deleted.clone().into_iter() {
+            let is_empty = self.territories.get(&id).is_some_and(|terr| terr.coords.is_empty());
+            if is_empty {
+                self.remove_territory(id);
+            } else if self.territories.contains_key(&id) {
+                plan.keep_deleted(id);
+            }
+        }
+
+        self.history.end_group();
+
+        Ok(plan.into_result())
+    }
+
+    /// Split territory into its 4-connected components. The largest
+    /// component keeps the original id, every other component is moved
+    /// into a new territory. Returns ids of the new territories (empty if
+    /// territory is already connected or does not exist).
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=splitDisconnected))]
+    pub fn split_disconnected(&mut self, id: u32) -> Vec<u32> {
+        self.history.begin_group();
+        let new_ids = self.split_disconnected_recorded(id);
+        self.history.end_group();
+        new_ids
+    }
+
+    /// Split all territories into their 4-connected components.
+    /// Returns mapping buffer, only containing territories that were split:
+    /// [
+    ///    id1, n1,              original id, number of new territories
+    ///    new(1,1), new(1,2),   new territory ids
+    ///    ...
+    ///    id2, n2,
+    ///    ...
+    /// ]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=splitAllDisconnected))]
+    pub fn split_all_disconnected(&mut self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.territories.keys().cloned().collect();
+        ids.sort();
+
+        let mut mapping: Vec<u32> = Vec::new();
+        self.history.begin_group();
+        for id in ids.into_iter() {
+            let new_ids = self.split_disconnected_recorded(id);
+            if new_ids.len() > 0 {
+                mapping.push(id);
+                mapping.push(new_ids.len() as u32);
+                mapping.extend(new_ids);
+            }
+        }
+        self.history.end_group();
+
+        mapping
+    }
+
+    /// Undo most recent changeset. Returns false if there is nothing
+    /// to undo or a group is still open.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn undo(&mut self) -> bool {
+        if let Some(changeset) = self.history.pop_undo() {
+            for change in changeset.changes.iter().rev() {
+                self.apply_change(change, true);
+            }
+            self.history.push_redo(changeset);
+            return true;
+        }
+        return false;
+    }
+
+    /// Redo most recently undone changeset. Returns false if there is
+    /// nothing to redo or a group is still open.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn redo(&mut self) -> bool {
+        if let Some(changeset) = self.history.pop_redo() {
+            for change in changeset.changes.iter() {
+                self.apply_change(change, false);
             }
+            self.history.push_undo(changeset);
+            return true;
         }
+        return false;
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=canUndo))]
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=canRedo))]
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    /// Begin group of edits that are undone/redone as a single step.
+    /// Groups can be nested, every call must be matched by `endGroup`.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=beginGroup))]
+    pub fn begin_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=endGroup))]
+    pub fn end_group(&mut self) {
+        self.history.end_group();
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=clearHistory))]
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Set max approximate memory in bytes used by undo/redo history.
+    /// Oldest changesets are dropped when over the limit.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=setHistoryMemoryLimit))]
+    pub fn set_history_memory_limit(&mut self, bytes: u32) {
+        self.history.set_memory_limit(bytes as usize);
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name=getHistoryMemoryUsage))]
+    pub fn get_history_memory_usage(&self) -> u32 {
+        self.history.memory_used() as u32
     }
+}
+
+// rust internal world operations that must run inside a history group
+impl World {
+    fn split_disconnected_recorded(&mut self, id: u32) -> Vec<u32> {
+        let components = match self.territories.get(&id) {
+            Some(territory) => territory.get_connected_components(),
+            None => return Vec::new(),
+        };
+
+        // keep largest component (first) in original territory
+        let mut new_ids: Vec<u32> = Vec::new();
+        for component in components.into_iter().skip(1) {
+            let new_id = match self.create_territory(None) {
+                Ok(new_id) => new_id,
+                Err(_) => break,
+            };
+            self.add_points_to_territory(new_id, component);
+            new_ids.push(new_id);
+        }
 
-    // print2(&format!("[{}] neighbors: {:?}", node_id, neighbors));
-    // print2(&format!("[{}] neighbor colors: {:?}", node_id, neighbor_colors));
+        new_ids
+    }
 
-    // try assigning color, then assign neighbors
-    'colors: for i in 0..MAX_COLORS {
-        if neighbor_colors[i] == false {
-            // assign node color
-            // print2(&format!("[{}]: try color: {}", node_id, i));
-            territories.get_mut(&node_id).unwrap().color = Some(i as u8);
+    fn merge_small_territories_recorded(
+        &mut self,
+        threshold: u32,
+        strategy: MergeStrategy,
+        selection: Option<&FnvHashSet<u32>>,
+    ) -> Vec<u32> {
+        let in_selection = |id: &u32| selection.map_or(true, |s| s.contains(id));
+
+        // min heap of (size, id) for territories smaller than threshold,
+        // entries are validated when popped since sizes change on merge
+        let mut queue: BinaryHeap<Reverse<(usize, u32)>> = self.territories.iter()
+            .filter(|(id, terr)| in_selection(id) && terr.coords.len() < threshold as usize)
+            .map(|(id, terr)| Reverse((terr.coords.len(), *id)))
+            .collect();
+
+        let mut removed: Vec<u32> = Vec::new();
+
+        while let Some(Reverse((size, id))) = queue.pop() {
+            let current_size = match self.territories.get(&id) {
+                Some(terr) => terr.coords.len(),
+                None => continue,
+            };
+            if current_size != size || current_size >= threshold as usize {
+                continue;
+            }
 
-            // assign color to un-assigned neighbors
-            for neighbor_id in neighbors.iter() {
-                if territories.get(&neighbor_id).unwrap().color.is_none() {
-                    if graph_color_recursive(territories, *neighbor_id) == false {
-                        continue 'colors;
+            // choose neighbor to merge into, ties broken by smaller id
+            let mut best: Option<(f64, u32)> = None;
+            if let Some(borders) = self.adjacency.borders(id) {
+                for (neighbor_id, border) in borders.iter() {
+                    if !in_selection(neighbor_id) {
+                        continue;
+                    }
+                    let neighbor_size = match self.territories.get(neighbor_id) {
+                        Some(terr) => terr.coords.len(),
+                        None => continue,
+                    };
+
+                    // higher score is better
+                    let score = match strategy {
+                        MergeStrategy::SmallestNeighbor => -(neighbor_size as f64),
+                        MergeStrategy::LongestBorder => *border as f64,
+                        MergeStrategy::MostCompact => {
+                            // Polsby-Popper 4 pi A / P^2 of merged territory
+                            let area = (current_size + neighbor_size) as f64;
+                            let perimeter = (self.adjacency.perimeter(id) + self.adjacency.perimeter(*neighbor_id) - 2 * border) as f64;
+                            4.0 * std::f64::consts::PI * area / (perimeter * perimeter)
+                        },
+                    };
+
+                    let better = match best {
+                        Some((best_score, best_id)) => score > best_score || (score == best_score && *neighbor_id < best_id),
+                        None => true,
+                    };
+                    if better {
+                        best = Some((score, *neighbor_id));
                     }
                 }
             }
 
-            return true;
+            // isolated small territory, keep as is
+            let target = match best {
+                Some((_, target)) => target,
+                None => continue,
+            };
+
+            if let Some(territory) = self.remove_territory(id) {
+                self.add_points_to_territory(target, territory.coords);
+                removed.push(id);
+            }
+
+            // merged cluster may still be too small
+            let target_size = self.territories[&target].coords.len();
+            if target_size < threshold as usize {
+                queue.push(Reverse((target_size, target)));
+            }
+        }
+
+        removed.sort();
+        removed
+    }
+
+    fn subdivide_into_random_territories_recorded(&mut self, id: u32, options: &GeneratorOptions) -> Option<Vec<u32>> {
+        if let Some(territory) = self.remove_territory(id) {
+            let coords: Vec<Point<i32>> = territory.coords.into_iter().collect();
+            return Some(self.generate_territories_from_coords_recorded(coords, options));
+        }
+
+        return None;
+    }
+
+    // partition unclaimed coords into random cells and create a new
+    // territory for each cell. returns ids of new territories.
+    fn generate_territories_from_coords_recorded(&mut self, coords: Vec<Point<i32>>, options: &GeneratorOptions) -> Vec<u32> {
+        if coords.is_empty() {
+            return Vec::new();
+        }
+
+        // get min/max from coords bounding box, slightly expand it
+        let aabb = AABB::from_points(coords.iter().cloned());
+        let min = ((aabb.min.x - 1) as f64, (aabb.min.y - 1) as f64);
+        let max = ((aabb.max.x + 1) as f64, (aabb.max.y + 1) as f64);
+
+        let mut random_cells: CellDiagram = generate_random_cells(
+            options.average_radius,
+            &min,
+            &max,
+            options.random_seed,
+            options.iterations_smooth_center,
+            options.iterations_smooth_corner,
+        );
+
+        if options.scale_x != 1.0 || options.scale_y != 1.0 {
+            // note: this does not properly update centroid positions
+            random_cells.scale((options.scale_x, options.scale_y));
+        }
+        random_cells.calculate_bounding_boxes();
+
+        let mut new_territories: Vec<Vec<Point<i32>>> = (0..random_cells.num_cells())
+            .map(|_| Vec::new())
+            .collect();
+
+        // assign to new territory
+        for p in coords.into_iter() {
+            if let Some(idx) = random_cells.cell_contains_coords(p.x as f64, p.y as f64) {
+                new_territories[idx].push(p);
+            }
+        }
+
+        let mut new_territory_ids: Vec<u32> = Vec::new();
+
+        for terr in new_territories.into_iter() {
+            if terr.len() > 0 {
+                // skip if delete created territories smaller than size
+                if options.delete_smaller_than > 0 && terr.len() < options.delete_smaller_than as usize {
+                    continue;
+                }
+
+                let id = match self.create_territory(None) {
+                    Ok(id) => id,
+                    Err(_) => break,
+                };
+                self.add_points_to_territory(id, terr);
+                new_territory_ids.push(id);
+            }
+        }
+
+        // run merging among new territories, if territory smaller
+        // than some value merge with SMALLEST neighbor
+        if options.merge_smaller_than > 0 {
+            let selection: FnvHashSet<u32> = new_territory_ids.iter().cloned().collect();
+            self.merge_small_territories_recorded(options.merge_smaller_than, MergeStrategy::SmallestNeighbor, Some(&selection));
+
+            // remove merged new territories
+            new_territory_ids.retain(|x| self.territories.contains_key(x));
+        }
+
+        return new_territory_ids;
+    }
+}
+
+
+// calculate territory neighbor ids and if territory is at edge
+// from scratch using territory neighboring points
+fn calculate_territory_neighbors(
+    terr: &Territory,
+    grid: &FnvHashMap<Point<i32>, u32>,
+    grid_occupied_coords: &FnvHashSet<Point<i32>>,
+) -> (FnvHashSet<u32>, bool) {
+    let neighbor_points = terr.get_neighboring_points();
+
+    // calculate territories from neighbor points, and check if any unclaimed
+    let mut is_at_edge: bool = false;
+    let mut neighbor_territories: FnvHashSet<u32> = FnvHashSet::default();
+    for p in neighbor_points.iter() {
+        if let Some(id) = grid.get(p) {
+            neighbor_territories.insert(*id);
+        }
+
+        if !grid_occupied_coords.contains(p) {
+            is_at_edge = true;
         }
     }
 
-    // failed to assign unique color in path, undo color and return false
-    // print2(&format!("failed, undoing color for id {:?}", node_id));
-    territories.get_mut(&node_id).unwrap().color = None;
-    return false;
+    (neighbor_territories, is_at_edge)
 }
 
 // graph O(n) 6-coloring strategy
@@ -645,6 +2164,7 @@ fn graph_color_recursive(territories: &mut FnvHashMap<u32, Territory>, node_id:
 // http://i.stanford.edu/pub/cstr/reports/cs/tr/80/830/CS-TR-80-830.pdf
 //
 // Returns map from territory id -> color integer
+#[allow(clippy::bool_comparison, clippy::needless_borrow, clippy::needless_range_loop, clippy::redundant_locals)]
 fn graph_6_coloring(territories: &FnvHashMap<u32, Territory>) -> FnvHashMap<u32, u8> {
 
     // graph vertex
@@ -780,91 +2300,1197 @@ fn graph_6_coloring(territories: &FnvHashMap<u32, Territory>) -> FnvHashMap<u32,
     return colors;
 }
 
-// TODO: solve graph with 5-coloring
-// apply linear time 5 coloring algorithm:
+// build symmetric territory neighbor graph for coloring,
+// without self loops or neighbors that are not in territories
+fn coloring_graph(territories: &FnvHashMap<u32, Territory>) -> FnvHashMap<u32, FnvHashSet<u32>> {
+    let mut graph: FnvHashMap<u32, FnvHashSet<u32>> = FnvHashMap::default();
+    for (id, terr) in territories.iter() {
+        graph.entry(*id).or_default();
+        for neighbor_id in terr.neighbors.iter() {
+            if *neighbor_id != *id && territories.contains_key(neighbor_id) {
+                graph.entry(*id).or_default().insert(*neighbor_id);
+                graph.entry(*neighbor_id).or_default().insert(*id);
+            }
+        }
+    }
+    return graph;
+}
+
+// graph linear time 5-coloring strategy for planar graphs
 // https://en.wikipedia.org/wiki/Five_color_theorem#Linear_time_five-coloring_algorithm
 // http://people.math.gatech.edu/~thomas/PAP/fcstoc.pdf
 // http://i.stanford.edu/pub/cstr/reports/cs/tr/80/830/CS-TR-80-830.pdf
-// fn graph_5_coloring() {
-    
-//     // graph vertex
-//     struct Vertex {
-//         id: u32,
-//         neighbors: FnvHashSet<u32>,
-//         merged: FnvHashSet<u32>,
-//     }
-
-//     // build clone of territories graph
-//     let mut graph: FnvHashMap<u32, Vertex> = FnvHashMap::default();
-
-//     // running list of vertices with degree < 5
-//     let mut vert_max_deg_4: Vec<u32> = Vec::new();
-
-//     // running list of vertices of degree = 5
-//     let mut vert_deg_5: Vec<u32> = Vec::new();
-
-//     // vertices removed from graph in order of removal
-//     let mut vert_removed: Vec<Vertex> = Vec::new();
-
-//     // first pass, add vertices to stacks matching conditions
-//     // and build clone of graph
-//     for (id, terr) in self.territories.iter() {
-//         let degree = terr.neighbors.len();
-//         if degree <= 4 {
-//             vert_max_deg_4.push(*id);
-//         }
-//         else if degree == 5 {
-//             vert_deg_5.push(*id);
-//         }
-
-//         graph.insert(*id, Vertex {
-//             id: *id,
-//             neighbors: terr.neighbors.clone(),
-//             merged: FnvHashSet::default(),
-//         });
-//     }
-
-//     // iteratively remove nodes from graph
-//     while graph.len() > 0 {
-//         // process vertices with deg < 5
-//         while let Some(vert_id) = vert_max_deg_4.pop() {
-//             // remove vert from graph
-//             let vert = graph.remove(&vert_id).unwrap();
-            
-//             // each neighbor degree reduced by 1
-//             // add neighbors to deg 4 or deg 5 stacks if they meet condition
-//             for neighbor_id in vert.neighbors.iter() {
-//                 let neighbor = graph.get_mut(&neighbor_id).unwrap();
-//                 neighbor.neighbors.remove(&vert_id);
-
-//                 // update neighbor degrees
-//                 let degree = neighbor.neighbors.len();
-//                 if degree < 4 && !vert_max_deg_4.contains(neighbor_id) {
-//                     vert_max_deg_4.push(*neighbor_id);
-//                 }
-//                 else if degree == 4 {
-//                     // previous degree must have been 5, remove from that stack
-//                     // and add to max deg 4 stack
-//                     let idx = vert_deg_5.iter().position(|&x| x == *neighbor_id).unwrap();
-//                     vert_deg_5.swap_remove(idx);
-//                     vert_max_deg_4.push(*neighbor_id);
-//                 }
-//                 else if degree == 5 {
-//                     vert_deg_5.push(*neighbor_id);
-//                 }
-
-//             }
-
-//             // mark removed vert
-//             vert_removed.push(vert);
-//         }
-
-//         // process vertices with deg == 5
-//         // TODO
-//         while let Some(vert_id) = vert_deg_5.pop() {
-//             // remove vert from graph
-//             let vert = graph.remove(&vert_id).unwrap();
-//             // TODO
-//         }
-//     }
-// }
\ No newline at end of file
+//
+// Iteratively reduce graph until empty:
+// 1. If vertex v with degree <= 4 exists, remove v from graph.
+// 2. Else a planar graph has a vertex v of degree 5 with two
+//    non-adjacent neighbors u, w of degree <= 7. Remove v, then
+//    merge u into w (w takes all of u's edges).
+// Then color vertices in reverse order of removal:
+// - merged vertex u takes color of w
+// - removed vertex v takes smallest color not used by neighbors
+//   it had when it was removed. v has at most 4 distinct neighbor
+//   colors because merged neighbors u, w share a color.
+//
+// Vertices are kept in degree bucket lists updated in O(1) on every
+// removal and merge. The smallest non-empty bucket is tracked by a
+// pointer that moves down only when a degree drops below it, so step
+// 1 finds a min degree vertex in amortized O(1).
+// Degree 5 vertices are re-checked for step 2 only when their degree
+// becomes 5 or a neighbor's degree drops to <= 7, each reduction does
+// constant work for planar graphs.
+//
+// Territory graphs can be non-planar (e.g. territories with
+// disconnected chunks), if no reduction is possible the smallest
+// degree vertex is removed instead (merging two of its non-adjacent
+// neighbors if it has degree 5), which may use more than 5 colors.
+//
+// Returns map from territory id -> color integer
+fn graph_5_coloring(territories: &FnvHashMap<u32, Territory>) -> FnvHashMap<u32, u8> {
+
+    // reduction step, in order applied
+    enum Step {
+        Removed { id: u32, neighbors: Vec<u32> },
+        Merged { id: u32, into: u32 },
+    }
+
+    // vertices bucketed by current degree, vertices are moved between
+    // bucket lists in O(1) and `min_degree` pointer only moves down when
+    // a vertex degree drops below it
+    struct DegreeBuckets {
+        buckets: Vec<Vec<u32>>,
+        // vertex id -> (degree, index in bucket list)
+        position: FnvHashMap<u32, (usize, usize)>,
+        // all buckets below this degree are empty
+        min_degree: usize,
+    }
+
+    impl DegreeBuckets {
+        fn set(&mut self, id: u32, degree: usize) {
+            self.remove(id);
+            if self.buckets.len() <= degree {
+                self.buckets.resize_with(degree + 1, Vec::new);
+            }
+            self.position.insert(id, (degree, self.buckets[degree].len()));
+            self.buckets[degree].push(id);
+            self.min_degree = self.min_degree.min(degree);
+        }
+
+        fn remove(&mut self, id: u32) {
+            if let Some((degree, index)) = self.position.remove(&id) {
+                let bucket = &mut self.buckets[degree];
+                bucket.swap_remove(index);
+                if let Some(moved) = bucket.get(index) {
+                    self.position.get_mut(moved).unwrap().1 = index;
+                }
+            }
+        }
+
+        // a vertex of smallest degree, with its degree
+        fn min(&mut self) -> Option<(u32, usize)> {
+            while self.min_degree < self.buckets.len() {
+                if let Some(id) = self.buckets[self.min_degree].last() {
+                    return Some((*id, self.min_degree));
+                }
+                self.min_degree += 1;
+            }
+            None
+        }
+    }
+
+    // find two non-adjacent neighbors of vertex, if `max_degree` is Some,
+    // both neighbors must have degree <= max_degree
+    fn find_merge_pair(id: u32, graph: &FnvHashMap<u32, FnvHashSet<u32>>, max_degree: Option<usize>) -> Option<(u32, u32)> {
+        let neighbors: Vec<u32> = graph.get(&id)?.iter().cloned().collect();
+        for i in 0..neighbors.len() {
+            for j in i+1..neighbors.len() {
+                let u = neighbors[i];
+                let w = neighbors[j];
+                let u_neighbors = &graph[&u];
+                if u_neighbors.contains(&w) {
+                    continue;
+                }
+                if let Some(max_degree) = max_degree {
+                    if u_neighbors.len() > max_degree || graph[&w].len() > max_degree {
+                        continue;
+                    }
+                }
+                return Some((u, w));
+            }
+        }
+        None
+    }
+
+    // vertex degree changed: update bucket, queue degree 5 vertices
+    // whose neighbors may now be mergeable
+    fn degree_changed(
+        id: u32,
+        prev_degree: usize,
+        graph: &FnvHashMap<u32, FnvHashSet<u32>>,
+        buckets: &mut DegreeBuckets,
+        deg_5: &mut Vec<u32>,
+    ) {
+        let neighbors = &graph[&id];
+        buckets.set(id, neighbors.len());
+        if neighbors.len() == 5 {
+            deg_5.push(id);
+        }
+        if neighbors.len() < prev_degree && neighbors.len() <= 7 {
+            deg_5.extend(neighbors.iter().filter(|n| graph[*n].len() == 5));
+        }
+    }
+
+    let mut graph = coloring_graph(territories);
+
+    let mut buckets = DegreeBuckets {
+        buckets: Vec::new(),
+        position: FnvHashMap::default(),
+        min_degree: 0,
+    };
+
+    // degree 5 candidates for step 2, validated when popped since
+    // vertex degrees change after they are pushed
+    let mut deg_5: Vec<u32> = Vec::new();
+
+    let mut ids: Vec<u32> = graph.keys().cloned().collect();
+    ids.sort();
+    for id in ids.into_iter() {
+        let degree = graph[&id].len();
+        buckets.set(id, degree);
+        if degree == 5 {
+            deg_5.push(id);
+        }
+    }
+
+    let mut steps: Vec<Step> = Vec::with_capacity(graph.len());
+
+    while let Some((min_id, min_degree)) = buckets.min() {
+        // 1. remove vertex with degree <= 4
+        let mut vert_id: Option<u32> = None;
+        let mut merge_pair: Option<(u32, u32)> = None;
+        if min_degree <= 4 {
+            vert_id = Some(min_id);
+        }
+
+        // 2. find degree 5 vertex with mergeable neighbors of degree <= 7
+        if vert_id.is_none() {
+            while let Some(id) = deg_5.pop() {
+                if graph.get(&id).is_some_and(|n| n.len() == 5) {
+                    if let Some(pair) = find_merge_pair(id, &graph, Some(7)) {
+                        vert_id = Some(id);
+                        merge_pair = Some(pair);
+                        break;
+                    }
+                }
+            }
+        }
+
+        // 3. non-planar fallback: remove smallest degree vertex
+        let vert_id = match vert_id {
+            Some(id) => id,
+            None => {
+                if min_degree == 5 {
+                    merge_pair = find_merge_pair(min_id, &graph, None);
+                }
+                min_id
+            },
+        };
+
+        // remove vertex, update neighbor degrees
+        let neighbors = graph.remove(&vert_id).unwrap();
+        buckets.remove(vert_id);
+        for neighbor_id in neighbors.iter() {
+            let neighbor_edges = graph.get_mut(neighbor_id).unwrap();
+            let prev_degree = neighbor_edges.len();
+            neighbor_edges.remove(&vert_id);
+            degree_changed(*neighbor_id, prev_degree, &graph, &mut buckets, &mut deg_5);
+        }
+        steps.push(Step::Removed {
+            id: vert_id,
+            neighbors: neighbors.into_iter().collect(),
+        });
+
+        // merge u into w
+        if let Some((u, w)) = merge_pair {
+            let u_neighbors = graph.remove(&u).unwrap();
+            buckets.remove(u);
+            let w_degree = graph[&w].len();
+            for x in u_neighbors.into_iter() {
+                let x_neighbors = graph.get_mut(&x).unwrap();
+                let prev_degree = x_neighbors.len();
+                x_neighbors.remove(&u);
+                if x_neighbors.insert(w) {
+                    graph.get_mut(&w).unwrap().insert(x);
+                }
+                degree_changed(x, prev_degree, &graph, &mut buckets, &mut deg_5);
+            }
+            degree_changed(w, w_degree, &graph, &mut buckets, &mut deg_5);
+            steps.push(Step::Merged {
+                id: u,
+                into: w,
+            });
+        }
+    }
+
+    // color vertices in reverse order of reduction
+    let mut colors: FnvHashMap<u32, u8> = FnvHashMap::default();
+
+    for step in steps.iter().rev() {
+        match step {
+            Step::Merged { id, into } => {
+                let color = colors[into];
+                colors.insert(*id, color);
+            },
+            Step::Removed { id, neighbors } => {
+                let mut neighbor_colors: Vec<bool> = vec![false; neighbors.len() + 1];
+                for neighbor_id in neighbors.iter() {
+                    let color = colors[neighbor_id] as usize;
+                    if color < neighbor_colors.len() {
+                        neighbor_colors[color] = true;
+                    }
+                }
+                let color = neighbor_colors.iter().position(|&used| !used).unwrap();
+                colors.insert(*id, color as u8);
+            },
+        }
+    }
+
+    return colors;
+}
+
+// stable graph coloring that keeps existing territory colors:
+// 1. Keep existing colors in 0..MAX_COLORS. While two neighbors share
+//    a kept color, uncolor the vertex with most conflicts (ties: newest
+//    id) so few vertices lose their color.
+// 2. Color each uncolored vertex (in id order) with smallest color not
+//    used by its neighbors.
+// 3. If all MAX_COLORS colors are used by neighbors, find Kempe chain:
+//    the connected component of vertices colored a or b containing the
+//    neighbors colored a. If chain contains no neighbor colored b, swap
+//    a <-> b in the chain, which frees color a. Smallest such chain is
+//    used to minimize changed colors.
+// 4. If no chain works (non-planar graph), use smallest free color,
+//    which may be >= MAX_COLORS.
+//
+// Returns map from territory id -> color integer
+fn graph_color_stable(territories: &FnvHashMap<u32, Territory>) -> FnvHashMap<u32, u8> {
+    let graph = coloring_graph(territories);
+
+    // keep valid existing colors
+    let mut colors: FnvHashMap<u32, u8> = FnvHashMap::default();
+    for (id, terr) in territories.iter() {
+        if let Some(color) = terr.color {
+            if (color as usize) < MAX_COLORS {
+                colors.insert(*id, color);
+            }
+        }
+    }
+
+    // count same color neighbors of each kept vertex
+    let mut conflicts: FnvHashMap<u32, u32> = FnvHashMap::default();
+    for (id, color) in colors.iter() {
+        let count = graph[id].iter()
+            .filter(|n| colors.get(n) == Some(color))
+            .count() as u32;
+        if count > 0 {
+            conflicts.insert(*id, count);
+        }
+    }
+
+    // uncolor vertices until no conflicts remain
+    while let Some((&id, _)) = conflicts.iter().max_by_key(|(id, count)| (**count, **id)) {
+        conflicts.remove(&id);
+        let color = colors.remove(&id).unwrap();
+        for neighbor_id in graph[&id].iter() {
+            if colors.get(neighbor_id) != Some(&color) {
+                continue;
+            }
+            let mut resolved = false;
+            if let Some(count) = conflicts.get_mut(neighbor_id) {
+                *count -= 1;
+                resolved = *count == 0;
+            }
+            if resolved {
+                conflicts.remove(neighbor_id);
+            }
+        }
+    }
+
+    // color uncolored vertices
+    let mut uncolored: Vec<u32> = graph.keys().filter(|id| !colors.contains_key(id)).cloned().collect();
+    uncolored.sort();
+
+    for id in uncolored.into_iter() {
+        let neighbors = &graph[&id];
+        let mut neighbor_colors: Vec<bool> = vec![false; neighbors.len() + 1];
+        for neighbor_id in neighbors.iter() {
+            if let Some(color) = colors.get(neighbor_id) {
+                let color = *color as usize;
+                if color < neighbor_colors.len() {
+                    neighbor_colors[color] = true;
+                }
+            }
+        }
+
+        let free_color = neighbor_colors.iter().position(|&used| !used).unwrap();
+        if free_color < MAX_COLORS {
+            colors.insert(id, free_color as u8);
+            continue;
+        }
+
+        // find smallest swappable kempe chain
+        let mut best_chain: Option<(u8, u8, Vec<u32>)> = None;
+        for a in 0..MAX_COLORS as u8 {
+            for b in 0..MAX_COLORS as u8 {
+                if a == b {
+                    continue;
+                }
+
+                let mut chain: Vec<u32> = Vec::new();
+                let mut visited: FnvHashSet<u32> = FnvHashSet::default();
+                let mut stack: Vec<u32> = neighbors.iter()
+                    .filter(|n| colors.get(n) == Some(&a))
+                    .cloned()
+                    .collect();
+                visited.extend(stack.iter().cloned());
+
+                let mut valid = true;
+                while let Some(v) = stack.pop() {
+                    if colors[&v] == b && neighbors.contains(&v) {
+                        valid = false;
+                        break;
+                    }
+                    chain.push(v);
+                    if best_chain.as_ref().is_some_and(|(_, _, best)| chain.len() >= best.len()) {
+                        valid = false;
+                        break;
+                    }
+                    for w in graph[&v].iter() {
+                        let color = colors.get(w);
+                        if (color == Some(&a) || color == Some(&b)) && visited.insert(*w) {
+                            stack.push(*w);
+                        }
+                    }
+                }
+
+                if valid {
+                    best_chain = Some((a, b, chain));
+                }
+            }
+        }
+
+        match best_chain {
+            Some((a, b, chain)) => {
+                for v in chain.into_iter() {
+                    let color = if colors[&v] == a { b } else { a };
+                    colors.insert(v, color);
+                }
+                colors.insert(id, a);
+            },
+            None => {
+                colors.insert(id, free_color as u8);
+            },
+        }
+    }
+
+    return colors;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use territory::territory::connected_components;
+
+    const TEST_WORLD_JSON: &str = include_str!("../../test/nodes/world.json");
+
+    fn test_world() -> World {
+        let mut world = World::from_world_json(TEST_WORLD_JSON, 16).unwrap();
+        world.calculate_neighbors();
+        world
+    }
+
+    // 3 x 3 grid of 4 x 4 chunk territories, ids 0..9
+    fn grid_world() -> World {
+        let mut world = World::new(16).unwrap();
+        for i in 0..9 {
+            let id = world.create_territory(None).unwrap();
+            let (x0, y0) = ((i % 3) * 4, (i / 3) * 4);
+            let coords: Vec<i32> = (0..16).flat_map(|j| vec![x0 + j % 4, y0 + j / 4]).collect();
+            world.add_coords_to_territory(id, coords);
+        }
+        world.calculate_neighbors();
+        world.clear_history();
+        world
+    }
+
+    // territory ids with sorted chunks, sorted by id
+    fn chunks(world: &World) -> Vec<(u32, Vec<(i32, i32)>)> {
+        let mut territories: Vec<(u32, Vec<(i32, i32)>)> = world.territories.values()
+            .map(|terr| {
+                let mut coords: Vec<(i32, i32)> = terr.coords.iter().map(|p| (p.x, p.y)).collect();
+                coords.sort();
+                (terr.id, coords)
+            })
+            .collect();
+        territories.sort();
+        territories
+    }
+
+    fn assert_valid_coloring(world: &World, max_colors: u8) {
+        for terr in world.territories.values() {
+            let color = terr.color.expect("territory not colored");
+            assert!(color < max_colors, "territory {} color {}", terr.id, color);
+            for neighbor in terr.neighbors.iter() {
+                assert_ne!(Some(color), world.territories[neighbor].color, "neighbors {} {}", terr.id, neighbor);
+            }
+        }
+    }
+
+    #[test]
+    fn undo_redo_round_trip() {
+        let mut world = grid_world();
+        let options = GeneratorOptions { random_seed: Some(1), average_radius: 2.0, ..GeneratorOptions::new() };
+
+        let mut states = vec![(chunks(&world), world.get_territory_id_counter())];
+        assert!(world.add_circle_to_territory(5, 12, 6, 3));
+        states.push((chunks(&world), world.get_territory_id_counter()));
+        world.remove_coords(vec![0, 0, 1, 0, 11, 11]);
+        states.push((chunks(&world), world.get_territory_id_counter()));
+        world.subdivide_into_random_territories(8, &options).unwrap();
+        states.push((chunks(&world), world.get_territory_id_counter()));
+        world.merge_territories(vec![0, 1, 3]).unwrap();
+        states.push((chunks(&world), world.get_territory_id_counter()));
+        world.delete_territory(2);
+        states.push((chunks(&world), world.get_territory_id_counter()));
+
+        for state in states.windows(2) {
+            assert_ne!(state[0], state[1]);
+        }
+
+        for state in states.iter().rev().skip(1) {
+            assert!(world.undo());
+            assert_eq!(*state, (chunks(&world), world.get_territory_id_counter()));
+        }
+        assert!(!world.undo());
+
+        for state in states.iter().skip(1) {
+            assert!(world.redo());
+            assert_eq!(*state, (chunks(&world), world.get_territory_id_counter()));
+        }
+        assert!(!world.redo());
+    }
+
+    #[test]
+    fn undo_restores_color_and_meta() {
+        let mut world = test_world();
+        let id = *world.territories.keys().min().unwrap();
+        let json = world.to_world_json();
+
+        let coords = world.get_territory_chunks_buffer(id);
+        world.remove_coords(coords);
+        world.delete_territory(id);
+        assert!(!world.territories.contains_key(&id));
+
+        world.undo();
+        world.undo();
+        // neighbors are not part of history
+        world.calculate_neighbors();
+        assert_eq!(json, world.to_world_json());
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut world = grid_world();
+        world.remove_coords(vec![0, 0]);
+        world.undo();
+        assert!(world.can_redo());
+        world.remove_coords(vec![1, 1]);
+        assert!(!world.can_redo());
+    }
+
+    #[test]
+    fn add_territory_is_one_undo_step() {
+        let mut world = grid_world();
+        let before = chunks(&world);
+
+        let mut terr = Territory::new(20);
+        terr.coords.insert(Point::new(0, 0));
+        terr.coords.insert(Point::new(20, 20));
+        world.add_territory(terr).unwrap();
+        assert_eq!(world.get_territory_id_counter(), 21);
+        assert_eq!(world.territory_at_chunk(0, 0), Some(20));
+        assert!(world.create_territory(None).unwrap() > 20);
+        world.undo();
+
+        assert!(world.undo());
+        assert_eq!(chunks(&world), before);
+        assert_eq!(world.get_territory_id_counter(), 9);
+        assert!(!world.undo());
+
+        assert!(world.add_territory(Territory::new(u32::MAX)).is_err());
+        assert_eq!(chunks(&world), before);
+    }
+
+    #[test]
+    fn world_json_round_trip() {
+        let world = test_world();
+        let json = world.to_world_json();
+        let reloaded = World::from_world_json(&json, 16).unwrap();
+        assert_eq!(chunks(&world), chunks(&reloaded));
+        assert_eq!(json, reloaded.to_world_json());
+
+        let original: Value = serde_json::from_str(TEST_WORLD_JSON).unwrap();
+        let saved: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(original["nodes"], saved["nodes"]);
+        assert_eq!(original["territories"]["0"]["name"], saved["territories"]["0"]["name"]);
+    }
+
+    #[test]
+    fn world_json_rejects_invalid() {
+        assert!(World::from_world_json("[]", 16).is_err());
+        assert!(World::from_world_json(r#"{"territories": {"a": {}}}"#, 16).is_err());
+        assert!(World::from_world_json(r#"{"territories": {"4294967295": {}}}"#, 16).is_err());
+        assert!(World::from_world_json(r#"{"territories": {"1": {"chunks": [4294967296, 0]}}}"#, 16).is_err());
+        assert!(World::from_world_json(r#"{"territories": {"1": {"color": 5}}}"#, 16).is_err());
+        assert!(World::from_world_json("{}", 0).is_err());
+
+        let duplicate = World::from_world_json(r#"{"territories": {"1": {"chunks": [0, 0]}, "01": {"chunks": [1, 0]}}}"#, 16);
+        assert!(matches!(duplicate, Err(WorldError::InvalidJson(_))));
+    }
+
+    #[test]
+    fn incremental_neighbors_match_full_rebuild() {
+        let mut world = grid_world();
+        world.remove_coords(vec![4, 4, 5, 4, 6, 4, 7, 4]);
+        world.add_coords_to_territory(0, vec![4, 0, 4, 1, 12, 0]);
+        world.delete_territory(8);
+        world.undo();
+        world.update_neighbors();
+
+        let incremental: Vec<(u32, Vec<u32>, bool)> = (0..9)
+            .map(|id| {
+                let terr = &world.territories[&id];
+                let mut neighbors: Vec<u32> = terr.neighbors.iter().cloned().collect();
+                neighbors.sort();
+                (id, neighbors, terr.is_at_edge)
+            })
+            .collect();
+        world.calculate_neighbors();
+        for (id, neighbors, is_at_edge) in incremental.into_iter() {
+            let terr = &world.territories[&id];
+            let mut expected: Vec<u32> = terr.neighbors.iter().cloned().collect();
+            expected.sort();
+            assert_eq!(neighbors, expected, "territory {}", id);
+            assert_eq!(is_at_edge, terr.is_at_edge, "territory {}", id);
+        }
+    }
+
+    #[test]
+    fn coloring_is_valid() {
+        let mut world = test_world();
+        world.split_all_disconnected();
+        world.calculate_neighbors();
+
+        world.generate_colors(Some(ColoringAlgorithm::Planar5));
+        assert_valid_coloring(&world, MAX_COLORS as u8);
+
+        world.generate_colors(Some(ColoringAlgorithm::Greedy6));
+        assert_valid_coloring(&world, 6);
+    }
+
+    #[test]
+    fn planar_coloring_of_brick_wall() {
+        // 2 x 1 chunk bricks, odd rows offset by one chunk, inner
+        // bricks have 6 neighbors so degree 5 reductions are needed
+        let mut world = World::new(16).unwrap();
+        for y in 0..12 {
+            for k in 0..12 {
+                let id = world.create_territory(None).unwrap();
+                let x = 2 * k + y % 2;
+                world.add_coords_to_territory(id, vec![x, y, x + 1, y]);
+            }
+        }
+        world.calculate_neighbors();
+        assert!(world.territories.values().any(|terr| terr.neighbors.len() == 6));
+
+        world.generate_colors(Some(ColoringAlgorithm::Planar5));
+        assert_valid_coloring(&world, MAX_COLORS as u8);
+    }
+
+    #[test]
+    fn stable_coloring_keeps_valid_colors() {
+        let mut world = grid_world();
+        world.generate_colors_stable();
+        assert_valid_coloring(&world, MAX_COLORS as u8);
+        assert!(world.generate_colors_stable().is_empty());
+
+        // conflicting color only recolors that territory
+        let center_color = world.territories[&4].color;
+        world.set_territory_color(1, center_color);
+        assert_eq!(world.generate_colors_stable(), vec![1]);
+        assert_valid_coloring(&world, MAX_COLORS as u8);
+    }
+
+    #[test]
+    fn flood_fill_claims_enclosed_region() {
+        let mut world = grid_world();
+        // open center of territory 4 into a 2 x 2 hole
+        world.remove_coords(vec![5, 5, 6, 5, 5, 6, 6, 6]);
+        assert_eq!(world.flood_fill_territory(4, 5, 5, None, None).unwrap(), 4);
+        assert_eq!(world.get_territory_size(4), Some(16));
+        assert!(world.undo());
+        assert_eq!(world.get_territory_size(4), Some(12));
+
+        assert!(matches!(world.flood_fill_territory(4, 5, 5, Some(3), None), Err(WorldError::FloodFillTooLarge(3))));
+        assert!(matches!(world.flood_fill_territory(4, 0, 0, None, None), Err(WorldError::ChunkClaimed(0, 0))));
+        assert!(matches!(world.flood_fill_territory(20, 5, 5, None, None), Err(WorldError::TerritoryNotFound(20))));
+    }
+
+    #[test]
+    fn flood_fill_rejects_unbounded_region() {
+        let mut world = grid_world();
+        assert!(matches!(world.flood_fill_territory(0, 12, 0, None, None), Err(WorldError::FloodFillUnbounded)));
+        assert!(matches!(world.flood_fill_territory(0, -1, 5, None, Some(vec![-2, -2, 14, 14])), Err(WorldError::FloodFillUnbounded)));
+        assert_eq!(world.get_territory_size(0), Some(16));
+        assert!(!world.can_undo());
+
+        // fill at i32 limits skips neighbors past the limit
+        let max = i32::MAX;
+        let bounds = Some(vec![max - 1, max - 1, max, max]);
+        assert!(matches!(world.flood_fill_territory(0, max, max, None, bounds), Err(WorldError::FloodFillUnbounded)));
+        let bounds = Some(vec![i32::MIN, i32::MIN, i32::MIN + 1, i32::MIN + 1]);
+        assert!(matches!(world.flood_fill_territory(0, i32::MIN, i32::MIN, None, bounds), Err(WorldError::FloodFillUnbounded)));
+    }
+
+    #[test]
+    fn dilate_and_erode_by_rings() {
+        let mut world = grid_world();
+
+        // corner territory grows only outwards into unclaimed chunks
+        assert!(world.dilate_territory(0, 1, None));
+        assert_eq!(world.get_territory_size(0), Some(16 + 8));
+        assert_eq!(world.get_territory_size(1), Some(16));
+        assert_eq!(world.get_territory_size(3), Some(16));
+
+        // center territory is enclosed, grows only when stealing
+        assert!(!world.dilate_territory(4, 1, None));
+        assert!(world.dilate_territory(4, 1, Some(true)));
+        assert_eq!(world.get_territory_size(4), Some(16 + 16));
+        assert_eq!(world.get_territory_size(1), Some(16 - 4));
+
+        // erode removes outer ring, then the rest
+        let mut world = grid_world();
+        assert!(world.erode_territory(4, 1));
+        let coords: Vec<(i32, i32)> = chunks(&world).into_iter().find(|(id, _)| *id == 4).unwrap().1;
+        assert_eq!(coords, vec![(5, 5), (5, 6), (6, 5), (6, 6)]);
+        assert!(world.erode_territory(4, 5));
+        assert_eq!(world.get_territory_size(4), Some(0));
+        assert!(!world.erode_territory(4, 1));
+    }
+
+    #[test]
+    fn smooth_removes_spikes_and_fills_notches() {
+        let mut world = World::new(16).unwrap();
+        let id = world.create_territory(None).unwrap();
+        // 5 x 5 square with notch at (0, 2) and spike at (5, 2)
+        let mut coords: Vec<i32> = Vec::new();
+        for x in 0..5 {
+            for y in 0..5 {
+                if (x, y) != (0, 2) {
+                    coords.extend_from_slice(&[x, y]);
+                }
+            }
+        }
+        coords.extend_from_slice(&[5, 2]);
+        world.add_coords_to_territory(id, coords);
+
+        assert!(world.smooth_territory(id, 1));
+        let expected: Vec<(i32, i32)> = (0..5).flat_map(|x| (0..5).map(move |y| (x, y))).collect();
+        assert_eq!(chunks(&world), vec![(id, expected)]);
+        assert!(!world.smooth_territory(id, 1));
+    }
+
+    #[test]
+    fn rebalance_keeps_territories_connected() {
+        let mut world = World::new(16).unwrap();
+        // 10 x 4 territory next to 2 x 4 and 1 x 4 territories
+        let widths = [(0, 10), (10, 2), (12, 1)];
+        for (x0, w) in widths.iter() {
+            let id = world.create_territory(None).unwrap();
+            let coords: Vec<i32> = (0..w * 4).flat_map(|j| vec![x0 + j % w, j / w]).collect();
+            world.add_coords_to_territory(id, coords);
+        }
+
+        let moved = world.rebalance_sizes(Vec::new(), None, 2);
+        assert!(moved > 0);
+
+        let target = 52.0 / 3.0;
+        for terr in world.territories.values() {
+            let size = terr.coords.len() as f64;
+            assert!((size - target).abs() <= 3.0, "territory {} size {}", terr.id, size);
+            assert_eq!(connected_components(&terr.coords).len(), 1, "territory {} split", terr.id);
+        }
+
+        // already balanced, nothing to move
+        assert_eq!(world.rebalance_sizes(Vec::new(), None, 2), 0);
+    }
+
+    // small territory 0 with neighbors: 1 large with border 2,
+    // 2 small with border 1, 3 medium with border 2 and compact merge
+    fn merge_strategy_world() -> World {
+        let mut world = World::new(16).unwrap();
+        let territories: Vec<Vec<i32>> = vec![
+            vec![0, 0, 0, 1],
+            (0..10).flat_map(|j| vec![1 + j % 5, j / 5]).collect(),
+            vec![0, 2, 0, 3, 0, 4],
+            vec![-2, 0, -1, 0, -2, 1, -1, 1],
+        ];
+        for coords in territories.into_iter() {
+            let id = world.create_territory(None).unwrap();
+            world.add_coords_to_territory(id, coords);
+        }
+        world
+    }
+
+    #[test]
+    fn merge_small_territories_by_strategy() {
+        let strategies = [
+            (MergeStrategy::SmallestNeighbor, 2),
+            (MergeStrategy::LongestBorder, 1),
+            (MergeStrategy::MostCompact, 3),
+        ];
+        for (strategy, target) in strategies.iter() {
+            let mut world = merge_strategy_world();
+            let size = world.get_territory_size(*target).unwrap();
+            assert_eq!(world.merge_small_territories(3, Some(*strategy), None), vec![0], "{:?}", strategy);
+            assert_eq!(world.get_territory_size(*target), Some(size + 2), "{:?}", strategy);
+        }
+
+        // territories outside selection are kept and not used as targets
+        let mut world = merge_strategy_world();
+        assert_eq!(world.merge_small_territories(3, None, Some(vec![0, 1])), vec![0]);
+        assert_eq!(world.get_territory_size(1), Some(12));
+    }
+
+    #[test]
+    fn merge_small_territories_merges_clusters() {
+        let mut world = World::new(16).unwrap();
+        for x in 0..4 {
+            let id = world.create_territory(None).unwrap();
+            world.add_coords_to_territory(id, vec![x, 0]);
+        }
+        // isolated small territory is kept
+        let isolated = world.create_territory(None).unwrap();
+        world.add_coords_to_territory(isolated, vec![10, 0]);
+
+        let removed = world.merge_small_territories(2, None, None);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(world.territories.len(), 3);
+        for terr in world.territories.values() {
+            if terr.id != isolated {
+                assert_eq!(terr.coords.len(), 2);
+            }
+        }
+        assert_eq!(world.get_territory_size(isolated), Some(1));
+    }
+
+    #[test]
+    fn fill_holes_by_size() {
+        let mut world = World::new(16).unwrap();
+        let id = world.create_territory(None).unwrap();
+        // 7 x 4 block with one chunk hole at (1, 1) and 2 x 2 hole at (3, 1)
+        let holes = [(1, 1), (3, 1), (4, 1), (3, 2), (4, 2)];
+        let coords: Vec<i32> = (0..7).flat_map(|x| (0..4).map(move |y| (x, y)))
+            .filter(|p| !holes.contains(p))
+            .flat_map(|(x, y)| vec![x, y])
+            .collect();
+        world.add_coords_to_territory(id, coords);
+
+        assert_eq!(world.fill_holes(id, Some(1)), 1);
+        assert_eq!(world.fill_holes(id, Some(1)), 0);
+        assert_eq!(world.fill_holes(id, None), 4);
+        assert_eq!(world.get_territory_size(id), Some(28));
+        assert_eq!(world.fill_holes(id, None), 0);
+    }
+
+    #[test]
+    fn fill_shared_holes_by_longest_border() {
+        let mut world = World::new(16).unwrap();
+        // hole at (1, 1) with 3 edges bordering territory 0, 1 edge territory 1
+        let a = world.create_territory(None).unwrap();
+        world.add_coords_to_territory(a, vec![0, 0, 0, 1, 0, 2, 1, 0, 1, 2]);
+        let b = world.create_territory(None).unwrap();
+        world.add_coords_to_territory(b, vec![2, 1]);
+
+        assert_eq!(world.fill_holes(a, None), 0);
+        assert_eq!(world.fill_all_holes(None, None), 0);
+        assert_eq!(world.fill_all_holes(None, Some(true)), 1);
+        assert_eq!(world.get_territory_size(a), Some(6));
+        assert_eq!(world.get_territory_size(b), Some(1));
+    }
+
+    #[test]
+    fn adjacency_graph_export() {
+        let world = grid_world();
+
+        let graph: serde_json::Value = serde_json::from_str(&world.get_adjacency_graph_json()).unwrap();
+        let nodes = graph["nodes"].as_array().unwrap();
+        let edges = graph["edges"].as_array().unwrap();
+        assert_eq!(nodes.len(), 9);
+        assert_eq!(nodes[4], serde_json::json!({ "id": 4, "size": 16 }));
+        // 3 x 3 grid has 12 edges, each shares a 4 chunk border
+        assert_eq!(edges.len(), 12);
+        assert_eq!(edges[0], serde_json::json!({ "source": 0, "target": 1, "weight": 4 }));
+
+        let dot = world.get_adjacency_graph_dot();
+        assert!(dot.starts_with("graph territories {\n"));
+        assert!(dot.contains("    4 [size=16];\n"));
+        assert!(dot.contains("    4 -- 5 [weight=4, label=4];\n"));
+        assert!(!dot.contains("    0 -- 4 "));
+    }
+
+    #[test]
+    fn adjacency_graph_queries() {
+        let mut world = grid_world();
+
+        assert_eq!(world.get_territory_path(0, 8), vec![0, 1, 2, 5, 8]);
+        assert_eq!(world.get_territory_path(4, 4), vec![4]);
+        assert_eq!(world.get_territory_path(0, 99), Vec::<u32>::new());
+
+        assert_eq!(world.get_territories_within_hops(4, 1), vec![1, 3, 5, 7]);
+        assert_eq!(world.get_territories_within_hops(0, 2), vec![1, 2, 3, 4, 6]);
+        assert_eq!(world.get_territories_within_hops(0, 0), Vec::<u32>::new());
+
+        // groups only use edges inside subset, largest first
+        assert_eq!(world.get_connected_territory_groups(vec![8, 0, 1, 2, 99]), vec![3, 0, 1, 2, 1, 8]);
+
+        // removing middle column splits the grid
+        let coords = world.get_territory_chunks_buffer(1);
+        world.remove_coords(coords);
+        let coords = world.get_territory_chunks_buffer(4);
+        world.remove_coords(coords);
+        let coords = world.get_territory_chunks_buffer(7);
+        world.remove_coords(coords);
+        assert_eq!(world.get_territory_path(0, 2), Vec::<u32>::new());
+        assert_eq!(world.get_territory_path(0, 6), vec![0, 3, 6]);
+    }
+
+    #[test]
+    fn distance_field_limits() {
+        let world = grid_world();
+
+        let field = world.distance_field(vec![4], 1, None).unwrap();
+        let chunks = field.get_chunks();
+        assert_eq!(chunks.len(), 3 * 32);
+        assert_eq!(&chunks[..3], &[4, 4, 0]);
+        assert_eq!(&chunks[chunks.len() - 3..], &[8, 7, 1]);
+        assert_eq!(field.get_territories(), vec![1, 1, 1, 3, 1, 1, 4, 0, 0, 5, 1, 1, 7, 1, 1]);
+
+        // claimed only reaches opposite corner through claimed chunks
+        let field = world.distance_field(vec![0], 1000, None).unwrap();
+        assert_eq!(field.get_chunks().len(), 3 * 144);
+        assert_eq!(&field.get_territories()[24..], &[8, 10, 16]);
+
+        // unclaimed chunks are visited, but distance is capped
+        let field = world.distance_field(vec![0], 1, Some(false)).unwrap();
+        assert_eq!(field.get_chunks().len(), 3 * 32);
+        assert!(field.get_chunks().chunks(3).any(|c| c == [-1, 0, 1]));
+        assert_eq!(field.get_territories(), vec![0, 0, 0, 1, 1, 1, 3, 1, 1]);
+        assert!(world.distance_field(vec![0], MAX_UNCLAIMED_DISTANCE, Some(false)).is_ok());
+        assert_eq!(
+            world.distance_field(vec![0], MAX_UNCLAIMED_DISTANCE + 1, Some(false)).err(),
+            Some(WorldError::DistanceTooLarge(MAX_UNCLAIMED_DISTANCE + 1, MAX_UNCLAIMED_DISTANCE)),
+        );
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut world = test_world();
+        world.generate_colors(Some(ColoringAlgorithm::Greedy6));
+        let empty_id = world.create_territory(None).unwrap();
+
+        let bytes = world.to_bytes();
+        let mut loaded = World::from_bytes(&bytes).unwrap();
+        assert_eq!(chunks(&world), chunks(&loaded));
+        assert_eq!(world.get_territory_id_counter(), loaded.get_territory_id_counter());
+        assert!(loaded.territories.contains_key(&empty_id));
+        for terr in world.territories.values() {
+            assert_eq!(terr.color, loaded.territories[&terr.id].color);
+            assert_eq!(terr.meta, loaded.territories[&terr.id].meta);
+        }
+
+        loaded.calculate_neighbors();
+        assert_eq!(world.to_world_json(), loaded.to_world_json());
+    }
+
+    #[test]
+    fn snapshot_rejects_truncated() {
+        let bytes = test_world().to_bytes();
+        for len in [0, 4, 5, bytes.len() / 2, bytes.len() - 1].iter() {
+            assert!(World::from_bytes(&bytes[..*len]).is_err(), "len {}", len);
+        }
+    }
+
+
+    #[test]
+    fn snapshot_rejects_unsorted_ids() {
+        let snapshot = |ids: &[u32]| {
+            let mut writer = SnapshotWriter::new();
+            writer.write_zigzag(16);
+            writer.write_varint(0);
+            writer.write_str("");
+            writer.write_varint(ids.len() as u64);
+            for (i, id) in ids.iter().enumerate() {
+                let mut coords: FnvHashSet<Point<i32>> = FnvHashSet::default();
+                coords.insert(Point::new(i as i32, 0));
+                writer.write_varint(*id as u64);
+                writer.write_varint(0);
+                writer.write_str("");
+                writer.write_chunks(&coords);
+            }
+            World::from_bytes(&writer.into_bytes())
+        };
+
+        assert_eq!(snapshot(&[1, 3]).unwrap().get_territory_id_counter(), 4);
+        assert!(matches!(snapshot(&[1, 1]), Err(WorldError::InvalidSnapshot(_))));
+        assert!(matches!(snapshot(&[3, 1]), Err(WorldError::InvalidSnapshot(_))));
+    }
+
+    #[test]
+    fn diff_apply_reproduces_edits() {
+        let base = grid_world();
+        let mut edited = grid_world();
+        let new_id = edited.create_territory(None).unwrap();
+        edited.set_territory_color(new_id, Some(3));
+        edited.territories.get_mut(&new_id).unwrap().meta.insert("name".to_string(), Value::from("new"));
+        edited.add_coords_to_territory(new_id, vec![20, 20, 21, 20]);
+        let coords = edited.get_territory_chunks_buffer(8);
+        edited.remove_coords(coords);
+        edited.delete_territory(8);
+        edited.remove_coords(vec![0, 0]);
+        edited.add_coords_to_territory(0, vec![-1, 0]);
+
+        let diff = base.diff(&edited);
+        assert_eq!(diff.created_ids(), vec![new_id]);
+        assert_eq!(diff.deleted(), &[8]);
+
+        let mut applied = grid_world();
+        applied.apply_diff(&diff).unwrap();
+        assert_eq!(chunks(&edited), chunks(&applied));
+        assert_eq!(applied.get_territory_color(new_id), Some(3));
+        assert_eq!(applied.territories[&new_id].meta.get("name"), Some(&Value::from("new")));
+        assert!(applied.diff(&edited).is_empty());
+
+        // one undo step
+        applied.undo();
+        assert_eq!(chunks(&base), chunks(&applied));
+    }
+
+    #[test]
+    fn diff_apply_rejects_conflicts() {
+        let base = grid_world();
+        let mut edited = grid_world();
+        edited.remove_coords(vec![0, 0]);
+        let coords = edited.get_territory_chunks_buffer(8);
+        edited.remove_coords(coords);
+        edited.delete_territory(8);
+        let diff = base.diff(&edited);
+
+        // chunk owner changed since diff
+        let mut world = grid_world();
+        world.remove_coords(vec![0, 0]);
+        let before = chunks(&world);
+        assert!(world.apply_diff(&diff).is_err());
+        assert_eq!(before, chunks(&world));
+
+        // deleted territory claimed a chunk not in diff
+        let mut world = grid_world();
+        world.add_coords_to_territory(8, vec![12, 8]);
+        match world.apply_diff(&diff) {
+            Err(WorldError::DiffTerritoryNotEmpty(8)) => (),
+            other => panic!("expected DiffTerritoryNotEmpty, got {:?}", other),
+        }
+        assert!(world.territories.contains_key(&8));
+    }
+
+    #[test]
+    fn merge_combines_edits() {
+        let mut base = grid_world();
+        let mut a = grid_world();
+        let mut b = grid_world();
+
+        // a: new territory and a moved chunk
+        let a_id = a.create_territory(None).unwrap();
+        a.add_coords_to_territory(a_id, vec![20, 0]);
+        a.remove_coords(vec![3, 0]);
+        a.add_coords_to_territory(1, vec![3, 0]);
+
+        // b: new territory with same id, and removed chunk
+        let b_id = b.create_territory(None).unwrap();
+        assert_eq!(a_id, b_id);
+        b.set_territory_color(b_id, Some(2));
+        b.add_coords_to_territory(b_id, vec![-5, -5]);
+        b.remove_coords(vec![11, 11]);
+
+        let result = base.merge(&a, &b).unwrap();
+        assert!(!result.has_conflicts());
+
+        let remapped = result.get_remapped();
+        assert_eq!(remapped.len(), 2);
+        assert_eq!(remapped[0], b_id);
+        let new_id = remapped[1];
+        assert!(new_id > a_id);
+
+        assert_eq!(base.territory_at_chunk(20, 0), Some(a_id));
+        assert_eq!(base.territory_at_chunk(3, 0), Some(1));
+        assert_eq!(base.territory_at_chunk(-5, -5), Some(new_id));
+        assert_eq!(base.get_territory_color(new_id), Some(2));
+        assert_eq!(base.territory_at_chunk(11, 11), None);
+        assert!(base.get_territory_id_counter() > new_id);
+    }
+
+    #[test]
+    fn merge_reports_chunk_conflicts() {
+        let mut base = grid_world();
+        let mut a = grid_world();
+        let mut b = grid_world();
+        a.remove_coords(vec![3, 0]);
+        a.add_coords_to_territory(1, vec![3, 0]);
+        b.remove_coords(vec![3, 0]);
+
+        let result = base.merge(&a, &b).unwrap();
+        assert!(result.has_conflicts());
+        assert_eq!(result.get_conflicts(), vec![3, 0, 1, 2, 0]);
+        // conflicting chunk keeps base owner
+        assert_eq!(base.territory_at_chunk(3, 0), Some(0));
+    }
+
+    #[test]
+    fn ownership_lookups_encode_id_plus_one() {
+        let world = grid_world();
+        assert_eq!(world.territory_at_chunk(4, 0), Some(1));
+        assert_eq!(world.territory_at_chunk(-1, 0), None);
+        assert_eq!(world.territories_at_chunks(vec![0, 0, 4, 0, -1, 0, 7]), vec![1, 2, 0]);
+
+        // block coords round down, also for negative coords
+        assert_eq!(world.territory_at_block(63, 0), Some(0));
+        assert_eq!(world.territory_at_block(64, 0), Some(1));
+        assert_eq!(world.territory_at_block(-1, 0), None);
+        assert_eq!(world.territories_at_blocks(vec![0, 191, -16, 0, 0, -1]), vec![7, 0, 0]);
+    }
+
+    #[test]
+    fn ownership_raster_and_downsampling() {
+        let world = grid_world();
+        let raster = world.ownership_raster(-1, 0, 4, 0).unwrap();
+        assert_eq!(raster, vec![0, 1, 1, 1, 1, 2]);
+        let raster = world.ownership_raster(3, 3, 4, 4).unwrap();
+        assert_eq!(raster, vec![1, 2, 4, 5]);
+
+        // 3 x 3 blocks of chunks starting at x = -1, last block is
+        // 2 x 3 with a tie between territory 2 and unclaimed
+        let raster = world.ownership_raster_downsampled(-1, 0, 12, 2, 3).unwrap();
+        assert_eq!(raster, vec![1, 1, 2, 3, 0]);
+
+        assert_eq!(world.ownership_raster(1, 0, 0, 0), Err(WorldError::InvalidBounds));
+        assert_eq!(world.ownership_raster_downsampled(0, 0, 4, 4, 0), Err(WorldError::InvalidDownsample(0)));
+        assert!(matches!(
+            world.ownership_raster(i32::MIN, i32::MIN, i32::MAX, i32::MAX),
+            Err(WorldError::RasterTooLarge(_, MAX_RASTER_SIZE)),
+        ));
+        // downsampled cost does not depend on block size
+        let raster = world.ownership_raster_downsampled(i32::MIN, i32::MIN, i32::MAX, i32::MAX, 1 << 22).unwrap();
+        assert_eq!(raster.len(), 1 << 20);
+        assert!(raster.iter().all(|v| *v == 0));
+        let raster = world.ownership_raster_downsampled(0, 0, 11, 11, 1 << 20).unwrap();
+        assert_eq!(raster, vec![1]);
+    }
+
+    #[test]
+    fn split_disconnected_keeps_largest_component() {
+        let mut world = grid_world();
+        world.add_coords_to_territory(0, vec![20, 20, 21, 20, 30, 30]);
+        assert!(world.split_disconnected(4).is_empty());
+
+        let new_ids = world.split_disconnected(0);
+        assert_eq!(new_ids.len(), 2);
+        assert_eq!(world.get_territory_size(0), Some(16));
+        let mut split: Vec<Vec<(i32, i32)>> = new_ids.iter()
+            .map(|id| {
+                assert!(*id >= 9);
+                let mut coords: Vec<(i32, i32)> = world.territories[id].coords.iter().map(|p| (p.x, p.y)).collect();
+                coords.sort();
+                coords
+            })
+            .collect();
+        split.sort();
+        assert_eq!(split, vec![vec![(20, 20), (21, 20)], vec![(30, 30)]]);
+
+        assert!(world.undo());
+        assert_eq!(world.get_territory_size(0), Some(19));
+        assert!(new_ids.iter().all(|id| !world.territories.contains_key(id)));
+    }
+
+    #[test]
+    fn split_all_disconnected_mapping() {
+        let mut world = grid_world();
+        world.add_coords_to_territory(2, vec![20, 0]);
+        world.add_coords_to_territory(7, vec![0, 20, 2, 20]);
+
+        let mapping = world.split_all_disconnected();
+        assert_eq!(mapping.len(), 2 + 1 + 2 + 2);
+        assert_eq!(mapping[0..2], [2, 1]);
+        assert_eq!(world.territory_at_chunk(20, 0), Some(mapping[2]));
+        assert_eq!(mapping[3..5], [7, 2]);
+        let mut new_ids = vec![mapping[5], mapping[6]];
+        new_ids.sort();
+        let mut owners = vec![world.territory_at_chunk(0, 20).unwrap(), world.territory_at_chunk(2, 20).unwrap()];
+        owners.sort();
+        assert_eq!(new_ids, owners);
+        assert!(world.split_all_disconnected().is_empty());
+    }
+
+    #[test]
+    fn generate_fills_only_unclaimed_chunks() {
+        let mut world = grid_world();
+        let options = GeneratorOptions { random_seed: Some(1), average_radius: 2.0, ..GeneratorOptions::new() };
+        let before: Vec<(u32, usize)> = (0..9).map(|id| (id, world.territories[&id].coords.len())).collect();
+
+        // region overlaps grid columns 8..12, claimed chunks untouched
+        let new_ids = world.generate_territories_in_region(Some(vec![8, 0, 15, 11]), None, &options).unwrap();
+        assert!(new_ids.len() > 0);
+        for (id, size) in before.iter() {
+            assert_eq!(world.territories[id].coords.len(), *size);
+        }
+        for id in new_ids.iter() {
+            for p in world.territories[id].coords.iter() {
+                assert!(p.x >= 12 && p.x <= 15 && p.y >= 0 && p.y <= 11, "{:?} outside unclaimed region", p);
+            }
+        }
+
+        // mask chunks outside bounds ignored, single undo removes all
+        let mut mask: Vec<i32> = (0..16).flat_map(|j| vec![j % 4, 12 + j / 4]).collect();
+        mask.extend_from_slice(&[20, 20]);
+        let new_ids = world.generate_territories_in_region(Some(vec![0, 12, 3, 15]), Some(mask), &options).unwrap();
+        assert!(new_ids.len() > 0);
+        for id in new_ids.iter() {
+            for p in world.territories[id].coords.iter() {
+                assert!(p.x >= 0 && p.x <= 3 && p.y >= 12 && p.y <= 15, "{:?} outside mask", p);
+            }
+        }
+        assert!(world.undo());
+        assert!(new_ids.iter().all(|id| !world.territories.contains_key(id)));
+    }
+
+    #[test]
+    fn generate_rejects_invalid_region() {
+        let mut world = grid_world();
+        let options = GeneratorOptions::new();
+        assert!(matches!(world.generate_territories_in_region(None, None, &options), Err(WorldError::InvalidBounds)));
+        assert!(matches!(world.generate_territories_in_region(Some(vec![4, 0, 0, 4]), None, &options), Err(WorldError::InvalidBounds)));
+        assert!(matches!(
+            world.generate_territories_in_region(Some(vec![0, 0, 1 << 12, 1 << 12]), None, &options),
+            Err(WorldError::RegionTooLarge(_, MAX_GENERATE_REGION_SIZE)),
+        ));
+    }
+}
@@ -0,0 +1,219 @@
+//! world_json.rs
+//! ----------------------------------------------------------------
+//! Conversion between world.json territory entries and Territory.
+//!
+//! world.json format:
+//! {
+//!     "meta": { "type": "world" },
+//!     "nodes": { name: resource node, ... },
+//!     "territories": {
+//!         "id": {
+//!             "name": name,
+//!             "core": [x, z] core block coords (or null),
+//!             "coreChunk": [x, z] core chunk coords (or null),
+//!             "chunks": [x1, z1, x2, z2, ...],
+//!             "size": number of chunks,
+//!             "neighbors": [id1, id2, ...],
+//!             "isEdge": bool,
+//!             "nodes": [node name, ...],
+//!             "color": color index 0..5 (or null),
+//!         },
+//!         ...
+//!     }
+//! }
+//!
+//! Fields owned by the World (chunks, size, neighbors, isEdge, color)
+//! are parsed into Territory. All other territory fields, including
+//! unknown plugin fields, are kept as raw json in `Territory::meta`
+//! so they round-trip losslessly.
+
+use std::convert::TryFrom;
+use serde_json::{Map, Number, Value};
+use territory::geometry::Point;
+use territory::territory::Territory;
+use territory::world::{MAX_COLORS, WorldError};
+
+// territory fields computed by the world, not stored in meta
+const TERRITORY_WORLD_FIELDS: [&str; 5] = ["chunks", "size", "neighbors", "isEdge", "color"];
+
+fn invalid_field(id: u32, field: &str) -> WorldError {
+    WorldError::InvalidJson(format!("territory {}: invalid \"{}\"", id, field))
+}
+
+/// Parse territory from a world.json territory object.
+pub fn territory_from_json(id: u32, value: &Value) -> Result<Territory, WorldError> {
+    let fields = value.as_object().ok_or_else(|| invalid_field(id, "territory"))?;
+
+    let mut territory = Territory::new(id);
+
+    // chunks flat buffer [x1, z1, x2, z2, ...]
+    match fields.get("chunks") {
+        Some(Value::Array(chunks)) => {
+            if chunks.len() % 2 != 0 {
+                return Err(invalid_field(id, "chunks"));
+            }
+            for i in (0..chunks.len()).step_by(2) {
+                let coord = |v: &Value| v.as_i64()
+                    .and_then(|c| i32::try_from(c).ok())
+                    .ok_or_else(|| invalid_field(id, "chunks"));
+                territory.coords.insert(Point::new(coord(&chunks[i])?, coord(&chunks[i+1])?));
+            }
+        },
+        None | Some(Value::Null) => (),
+        Some(_) => return Err(invalid_field(id, "chunks")),
+    }
+
+    match fields.get("neighbors") {
+        Some(Value::Array(neighbors)) => {
+            for v in neighbors.iter() {
+                let neighbor = v.as_u64()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .ok_or_else(|| invalid_field(id, "neighbors"))?;
+                territory.neighbors.insert(neighbor);
+            }
+        },
+        None | Some(Value::Null) => (),
+        Some(_) => return Err(invalid_field(id, "neighbors")),
+    }
+
+    match fields.get("isEdge") {
+        Some(Value::Bool(is_edge)) => territory.is_at_edge = *is_edge,
+        None | Some(Value::Null) => (),
+        Some(_) => return Err(invalid_field(id, "isEdge")),
+    }
+
+    match fields.get("color") {
+        Some(Value::Number(color)) => {
+            let color = color.as_u64().filter(|&c| c < MAX_COLORS as u64).ok_or_else(|| invalid_field(id, "color"))?;
+            territory.color = Some(color as u8);
+        },
+        None | Some(Value::Null) => (),
+        Some(_) => return Err(invalid_field(id, "color")),
+    }
+
+    // keep everything else as raw json
+    for (key, v) in fields.iter() {
+        if !TERRITORY_WORLD_FIELDS.contains(&key.as_str()) {
+            territory.meta.insert(key.clone(), v.clone());
+        }
+    }
+
+    Ok(territory)
+}
+
+/// Serialize territory into a world.json territory object.
+/// Chunks and neighbors are sorted so output is deterministic.
+pub fn territory_to_json(territory: &Territory) -> Value {
+    let mut fields = territory.meta.clone();
+
+    // defaults matching editor output for territories created in world
+    fields.entry("name").or_insert_with(|| Value::String(String::new()));
+    fields.entry("core").or_insert(Value::Null);
+    fields.entry("coreChunk").or_insert(Value::Null);
+    fields.entry("nodes").or_insert_with(|| Value::Array(Vec::new()));
+
+    let mut coords: Vec<&Point<i32>> = territory.coords.iter().collect();
+    coords.sort_by_key(|p| (p.x, p.y));
+    let mut chunks: Vec<Value> = Vec::with_capacity(2 * coords.len());
+    for p in coords.into_iter() {
+        chunks.push(Value::from(p.x));
+        chunks.push(Value::from(p.y));
+    }
+
+    let mut neighbors: Vec<u32> = territory.neighbors.iter().cloned().collect();
+    neighbors.sort();
+
+    fields.insert("chunks".to_string(), Value::Array(chunks));
+    fields.insert("size".to_string(), Value::from(territory.coords.len()));
+    fields.insert("neighbors".to_string(), Value::Array(neighbors.into_iter().map(Value::from).collect()));
+    fields.insert("isEdge".to_string(), Value::Bool(territory.is_at_edge));
+    fields.insert("color".to_string(), match territory.color {
+        Some(c) => Value::Number(Number::from(c)),
+        None => Value::Null,
+    });
+
+    Value::Object(fields)
+}
+
+/// Default world.json "meta" field
+pub fn default_world_meta() -> Value {
+    let mut meta = Map::new();
+    meta.insert("type".to_string(), Value::String("world".to_string()));
+    Value::Object(meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn territory_round_trip() {
+        let json: Value = serde_json::from_str(r#"{
+            "name": "capital",
+            "core": [16, -32],
+            "coreChunk": [1, -2],
+            "chunks": [1, -2, 0, 0, 1, 0],
+            "size": 3,
+            "neighbors": [9, 4],
+            "isEdge": true,
+            "nodes": ["wheat"],
+            "color": 3,
+            "plugin": { "owner": "town" }
+        }"#).unwrap();
+
+        let territory = territory_from_json(12, &json).unwrap();
+        assert_eq!(territory.id, 12);
+        assert_eq!(territory.coords.len(), 3);
+        assert!(territory.coords.contains(&Point::new(1, -2)));
+        assert_eq!(territory.color, Some(3));
+        assert!(territory.is_at_edge);
+        assert_eq!(territory.meta.get("plugin"), json.get("plugin"));
+        assert!(!territory.meta.contains_key("chunks"));
+
+        let saved = territory_to_json(&territory);
+        assert_eq!(saved["chunks"], serde_json::json!([0, 0, 1, -2, 1, 0]));
+        assert_eq!(saved["neighbors"], serde_json::json!([4, 9]));
+        for key in ["name", "core", "coreChunk", "size", "isEdge", "nodes", "color", "plugin"].iter() {
+            assert_eq!(saved[key], json[key], "{}", key);
+        }
+    }
+
+    #[test]
+    fn territory_defaults() {
+        let territory = territory_from_json(0, &serde_json::json!({})).unwrap();
+        assert!(territory.coords.is_empty());
+        assert_eq!(territory.color, None);
+
+        let saved = territory_to_json(&territory);
+        assert_eq!(saved["name"], "");
+        assert_eq!(saved["core"], Value::Null);
+        assert_eq!(saved["color"], Value::Null);
+        assert_eq!(saved["nodes"], serde_json::json!([]));
+
+        let territory = territory_from_json(0, &serde_json::json!({ "chunks": null, "color": null })).unwrap();
+        assert!(territory.coords.is_empty());
+        assert_eq!(territory.color, None);
+    }
+
+    #[test]
+    fn territory_rejects_invalid() {
+        let invalid = [
+            r#"[]"#,
+            r#"{"chunks": {}}"#,
+            r#"{"chunks": [0]}"#,
+            r#"{"chunks": [0, "1"]}"#,
+            r#"{"chunks": [2147483648, 0]}"#,
+            r#"{"chunks": [0, -2147483649]}"#,
+            r#"{"neighbors": [-1]}"#,
+            r#"{"neighbors": [4294967296]}"#,
+            r#"{"isEdge": 1}"#,
+            r#"{"color": 5}"#,
+            r#"{"color": 256}"#,
+            r#"{"color": "red"}"#,
+        ];
+        for json in invalid.iter() {
+            let value: Value = serde_json::from_str(json).unwrap();
+            assert!(territory_from_json(0, &value).is_err(), "{}", json);
+        }
+    }
+}
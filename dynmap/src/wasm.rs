@@ -0,0 +1,57 @@
+//! wasm.rs
+//! ----------------------------------------------------------------
+//! Glue between the territory engine and the browser, only compiled
+//! with the `wasm` feature. Territory types are exported to js with
+//! `#[cfg_attr(feature = "wasm", wasm_bindgen)]` attributes in their
+//! modules, this adds the js side parts that do not belong in the
+//! plain rust library: console logging and error conversion.
+
+use wasm_bindgen::prelude::*;
+use territory::log::{LogLevel, set_log_hook};
+use territory::world::WorldError;
+
+#[wasm_bindgen]
+extern {
+    #[wasm_bindgen(js_namespace = console)]
+    fn log(s: &str);
+
+    #[wasm_bindgen(js_namespace = console)]
+    fn error(s: &str);
+}
+
+fn console_log_hook(level: LogLevel, msg: &str) {
+    match level {
+        LogLevel::Error => error(msg),
+        _ => log(msg),
+    }
+}
+
+/// Runs when wasm module is loaded, sends info and error logs to console.
+#[wasm_bindgen(start)]
+pub fn start() {
+    set_log_hook(Some(console_log_hook), LogLevel::Info);
+}
+
+/// Set min level of logs sent to console:
+/// 0 = debug, 1 = info, 2 = error, undefined = logging off.
+#[wasm_bindgen(js_name=setLogLevel)]
+pub fn set_log_level(level: Option<u8>) {
+    match level {
+        Some(0) => set_log_hook(Some(console_log_hook), LogLevel::Debug),
+        Some(1) => set_log_hook(Some(console_log_hook), LogLevel::Info),
+        Some(_) => set_log_hook(Some(console_log_hook), LogLevel::Error),
+        None => set_log_hook(None, LogLevel::Error),
+    }
+}
+
+impl From<WorldError> for JsValue {
+    fn from(err: WorldError) -> JsValue {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+// test function
+#[wasm_bindgen]
+pub fn greet() {
+    log("hello world!");
+}